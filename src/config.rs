@@ -1,64 +1,445 @@
 use crate::{
+    definitions::Definitions,
     exports::ExportItem,
+    ops,
     sources::Loader,
+    state::{self, StateConfig},
     transforms::{Transform, TransformItem},
     utils::{with_current_dir, Error},
 };
-use anyhow::Result;
-use polars::lazy::prelude::*;
+use anyhow::{Context as _, Result};
+use polars::{lazy::prelude::*, prelude::Schema};
+use regex::{Captures, Regex};
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, path::Path};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+thread_local! {
+    /// Canonical paths of configuration files whose `extends` chains are currently being resolved.
+    static EXTENDS_CHAIN: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+    /// Variables that override those defined by any configuration loaded, set by [`Config::with_vars`].
+    static VAR_OVERRIDES: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+}
 
 /// Configuration to load data, apply transformations, and export to one or multiple destinations.
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Config {
+    /// Path to a base configuration to extend, relative to this file.
+    /// The base's transforms and exports run before this configuration's own, and its source is used
+    /// unless this configuration defines one.
+    pub extends: Option<PathBuf>,
+    /// Variables to substitute for `${name}` placeholders anywhere in this file, before it is parsed.
+    /// A placeholder that is not defined here is read from the environment, and values given with
+    /// [`Config::with_vars`] (or `--var` on the command line) take precedence over both.
+    /// Write `$${` for a literal `${`. These are not inherited via `extends`.
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    /// Named loaders and transform lists that can be referenced elsewhere in this configuration.
+    /// Definitions in this configuration take precedence over those with the same name in a base configuration.
+    #[serde(default)]
+    pub definitions: Definitions,
     /// The top-level source that data should be loaded from.
-    pub source: Loader,
+    /// May only be omitted if a configuration being extended defines one.
+    pub source: Option<Loader>,
     /// Transformations to apply to the data loaded from the source.
     #[serde(default)]
     pub transforms: Vec<TransformItem>,
     /// Export destinations for the transformed data.
     #[serde(default)]
     pub exports: Vec<ExportItem>,
+    /// Track which input files have been processed, so that subsequent runs only load new or changed files.
+    pub state: Option<StateConfig>,
+    /// Options controlling how the configuration is run. These are not inherited via `extends`.
+    #[serde(default)]
+    pub run: RunOptions,
+}
+
+/// Options controlling how a configuration is run.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct RunOptions {
+    /// Run exports concurrently on separate threads rather than one after another.
+    #[serde(default)]
+    pub parallel: bool,
+    /// Collect the transformed data into memory once and export that, rather than executing the pipeline
+    /// separately for each export. This avoids repeating expensive transforms when there are multiple exports,
+    /// at the cost of holding the entire result in memory instead of streaming it to each destination.
+    #[serde(default)]
+    pub materialize_once: bool,
+    /// Write a `<filename>.manifest.json` file next to each exported file, recording its path, row count, schema,
+    /// size and when it was written. Counting rows evaluates the data again for exports that stream it,
+    /// unless `materialize_once` is also set.
+    #[serde(default)]
+    pub write_manifest: bool,
+    /// Time the source and each transform separately and log a summary once the data is loaded.
+    /// To measure each stage, its output is collected into memory before the next stage runs,
+    /// so profiled runs are slower and use more memory than normal runs.
+    #[serde(default)]
+    pub profile: bool,
+    /// Seed for random operations such as `shuffle`, so that repeated runs, and `inspect` or `--preview`,
+    /// produce the same output.
+    pub seed: Option<u64>,
+    /// Directory to resolve relative paths in this configuration against, such as those of sources,
+    /// exports and state, instead of the directory containing the configuration file.
+    /// This path itself and `extends` are always relative to the configuration file.
+    pub base_dir: Option<PathBuf>,
+    /// Query optimizations to enable or disable, for working around an optimization that causes errors
+    /// or changes results.
+    #[serde(default)]
+    pub optimizations: Optimizations,
+}
+
+/// Query optimizations applied by polars. Each defaults to the polars default if not set.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct Optimizations {
+    /// Apply filters as early as possible, such as while reading files.
+    pub predicate_pushdown: Option<bool>,
+    /// Only read the columns that are needed.
+    pub projection_pushdown: Option<bool>,
+    /// Only read the rows that are needed when the data is sliced, such as by a `limit`.
+    pub slice_pushdown: Option<bool>,
+    /// Compute parts of the query that are shared between branches, such as both sides of a join, only once.
+    pub comm_subplan_elim: Option<bool>,
+}
+
+impl Optimizations {
+    /// Apply these settings to `lf`.
+    fn apply(&self, mut lf: LazyFrame) -> LazyFrame {
+        if let Some(enabled) = self.predicate_pushdown {
+            lf = lf.with_predicate_pushdown(enabled);
+        }
+        if let Some(enabled) = self.projection_pushdown {
+            lf = lf.with_projection_pushdown(enabled);
+        }
+        if let Some(enabled) = self.slice_pushdown {
+            lf = lf.with_slice_pushdown(enabled);
+        }
+        if let Some(enabled) = self.comm_subplan_elim {
+            lf = lf.with_comm_subplan_elim(enabled);
+        }
+        lf
+    }
+}
+
+/// The options that must be read before the rest of a configuration can be parsed,
+/// since parsing resolves relative paths.
+#[derive(Deserialize, Default)]
+struct Preamble {
+    #[serde(default)]
+    vars: BTreeMap<String, String>,
+    #[serde(default)]
+    run: PreambleRunOptions,
+}
+
+#[derive(Deserialize, Default)]
+struct PreambleRunOptions {
+    base_dir: Option<PathBuf>,
+}
+
+/// Timings of each stage of a configuration, recorded when [`RunOptions::profile`] is set.
+#[derive(Default)]
+struct Profiler {
+    stages: Vec<Stage>,
+}
+
+/// Timing of a single stage of a configuration.
+struct Stage {
+    name: String,
+    elapsed: Duration,
+    rows_in: Option<usize>,
+    rows_out: usize,
+}
+
+impl Profiler {
+    /// Collect the output of a stage that started at `start`, recording how long it took and how many rows it produced.
+    fn stage(&mut self, name: String, start: Instant, lf: LazyFrame) -> Result<LazyFrame> {
+        let df = lf.collect().with_context(|| name.clone())?;
+        self.stages.push(Stage {
+            rows_in: self.stages.last().map(|stage| stage.rows_out),
+            rows_out: df.height(),
+            elapsed: start.elapsed(),
+            name,
+        });
+        Ok(df.lazy())
+    }
+
+    /// Log the recorded timings.
+    fn report(self) {
+        for stage in self.stages.iter() {
+            match stage.rows_in {
+                Some(rows_in) => info!(
+                    "{}: {:?}, {} -> {} rows",
+                    stage.name, stage.elapsed, rows_in, stage.rows_out
+                ),
+                None => info!(
+                    "{}: {:?}, {} rows",
+                    stage.name, stage.elapsed, stage.rows_out
+                ),
+            }
+        }
+        let total: Duration = self.stages.iter().map(|stage| stage.elapsed).sum();
+        info!("total: {:?}", total);
+    }
 }
 
 impl Config {
+    /// Create a configuration that loads data from `source`, with no transforms or exports.
+    /// Transforms and exports can then be added with [`Config::with_transform`] and [`Config::with_export`]
+    /// to build a pipeline programmatically rather than from a configuration file.
+    ///
+    /// ```
+    /// use polars::prelude::*;
+    /// use retl::{
+    ///     expressions::{Column, ExpressionChain, Literal},
+    ///     ops::OpItem,
+    ///     sources::{DataSource, InlineSource, Loader},
+    ///     transforms::{Filter, Select},
+    ///     Config,
+    /// };
+    ///
+    /// let df = df!("name" => ["a", "b", "c"], "n" => [1u32, 2, 3])?;
+    /// let config = Config::new(Loader::new(DataSource::Inline(InlineSource::new(df))))
+    ///     .with_transform(Filter::new([ExpressionChain::new(Column::new("name"))
+    ///         .with_op(OpItem::Gt(ExpressionChain::new(Literal::new("a"))))]))
+    ///     .with_transform(Select::new([ExpressionChain::new(Column::new("n"))]));
+    /// let df = config.load()?.collect()?;
+    /// assert_eq!(df.column("n")?.u32()?.to_vec(), [Some(2), Some(3)]);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn new(source: Loader) -> Self {
+        Self {
+            extends: None,
+            vars: BTreeMap::new(),
+            definitions: Definitions::default(),
+            source: Some(source),
+            transforms: Vec::new(),
+            exports: Vec::new(),
+            state: None,
+            run: RunOptions::default(),
+        }
+    }
+    /// Add a transform to the end of the pipeline.
+    pub fn with_transform<T: Into<TransformItem>>(mut self, transform: T) -> Self {
+        self.transforms.push(transform.into());
+        self
+    }
+    /// Add an export destination.
+    pub fn with_export(mut self, export: ExportItem) -> Self {
+        self.exports.push(export);
+        self
+    }
     /// Load the end result without exporting.
     pub fn load(&self) -> Result<LazyFrame> {
-        let mut lf: LazyFrame = self.source.load()?;
-        for t in self.transforms.iter() {
-            lf = t.transform(lf)?;
+        let build = || {
+            self.definitions.scope(|| {
+                let mut profiler = self.run.profile.then(Profiler::default);
+                let start = Instant::now();
+                let mut lf: LazyFrame = self
+                    .source
+                    .as_ref()
+                    .ok_or(Error::NoSource)?
+                    .load()
+                    .context("source")?;
+                lf = self.run.optimizations.apply(lf);
+                if let Some(profiler) = profiler.as_mut() {
+                    lf = profiler.stage("source".to_owned(), start, lf)?;
+                }
+                for (i, t) in self.transforms.iter().enumerate() {
+                    let start = Instant::now();
+                    // Only the source's own files are tracked, not those of sources loaded by transforms.
+                    lf = state::untracked(|| t.transform(lf))
+                        .with_context(|| format!("transforms[{i}]"))?;
+                    // Transforms that collect or combine data can produce a frame without these settings.
+                    lf = self.run.optimizations.apply(lf);
+                    if let Some(profiler) = profiler.as_mut() {
+                        lf = profiler.stage(format!("transforms[{i}]"), start, lf)?;
+                    }
+                }
+                if let Some(profiler) = profiler {
+                    profiler.report();
+                }
+                Ok(lf)
+            })
+        };
+        // Random operations draw their seeds while the plan is built, so a nested configuration
+        // without its own seed continues from the seed of the configuration loading it.
+        match self.run.seed {
+            Some(seed) => ops::with_seed(seed, build),
+            None => build(),
         }
-        Ok(lf)
+    }
+    /// Resolve the schema of the end result from the query plan, without reading any data.
+    pub fn schema(&self) -> Result<Schema> {
+        Ok(self.load()?.collect_schema()?.as_ref().clone())
     }
     /// Run the configuration, exporting the transformed data.
     pub fn run(&self) -> Result<()> {
+        self.run_with(|_| Ok(()))
+    }
+    /// Run the configuration, passing the loaded data to `inspect` before it is exported.
+    pub fn run_with<F>(&self, inspect: F) -> Result<()>
+    where
+        F: FnOnce(&LazyFrame) -> Result<()>,
+    {
         if self.exports.is_empty() {
             return Err(Error::NoExports.into());
         }
-        let lf = self.load()?;
-        for e in self.exports.iter() {
-            e.export(lf.clone())?;
+        let load_and_export = || {
+            let lf = self.load()?;
+            inspect(&lf)?;
+            self.export(lf)
+        };
+        match &self.state {
+            Some(state) => state.track(load_and_export),
+            None => load_and_export(),
+        }
+    }
+    /// Export the given data to each destination.
+    pub(crate) fn export(&self, mut lf: LazyFrame) -> Result<()> {
+        if self.run.materialize_once {
+            lf = lf.collect()?.lazy();
+        }
+        let reports = if self.run.parallel {
+            std::thread::scope(|scope| {
+                self.exports
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| {
+                        let lf = lf.clone();
+                        scope.spawn(move || e.export(lf).with_context(|| format!("exports[{i}]")))
+                    })
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .map(|handle| handle.join().expect("export thread panicked"))
+                    .collect::<Result<Vec<_>>>()
+            })?
+        } else {
+            self.exports
+                .iter()
+                .enumerate()
+                .map(|(i, e)| {
+                    e.export(lf.clone())
+                        .with_context(|| format!("exports[{i}]"))
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        if self.run.write_manifest {
+            for report in reports.iter() {
+                report.write_manifest(&lf)?;
+            }
         }
         Ok(())
     }
+    /// Run `func` with the given variables taking precedence over those defined by any configuration it loads.
+    pub fn with_vars<T, F>(vars: BTreeMap<String, String>, func: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let previous = VAR_OVERRIDES.replace(vars);
+        let result = func();
+        VAR_OVERRIDES.set(previous);
+        result
+    }
     /// Load a configuration from the given path.
+    /// Files ending in `.yaml` or `.yml` are parsed as YAML, `.json` as JSON, and anything else as TOML.
     pub fn from_path<P, F, R>(path: P, func: F) -> Result<R>
     where
         P: AsRef<Path>,
         F: Fn(Self) -> Result<R>,
     {
-        let canonical_path = path.as_ref().canonicalize()?;
-        let file = std::fs::read_to_string(&canonical_path)?;
-        with_current_dir(
-            canonical_path
-                .parent()
-                .expect("path cannot be filesystem root"),
-            move || {
-                let config: Self = toml::from_str(&file)?;
-                func(config)
-            },
-        )
+        let canonical_path = path
+            .as_ref()
+            .canonicalize()
+            .with_context(|| format!("config {}", path.as_ref().display()))?;
+        let file = std::fs::read_to_string(&canonical_path)
+            .with_context(|| format!("config {}", canonical_path.display()))?;
+        let dir = canonical_path
+            .parent()
+            .expect("path cannot be filesystem root");
+        with_current_dir(dir, || {
+            let vars = Self::parse::<Preamble>(&canonical_path, &file)?.vars;
+            let file = substitute_vars(&file, &vars)?;
+            let preamble: Preamble = Self::parse(&canonical_path, &file)?;
+            let base_dir = match preamble.run.base_dir {
+                Some(base_dir) => base_dir
+                    .canonicalize()
+                    .with_context(|| format!("run.base_dir {}", base_dir.display()))?,
+                None => dir.to_owned(),
+            };
+            with_current_dir(&base_dir, || {
+                let config: Self = Self::parse(&canonical_path, &file)?;
+                func(config.resolve_extends(&canonical_path)?)
+            })
+        })
+        .with_context(|| format!("config {}", canonical_path.display()))
+    }
+    /// Parse a configuration in the format indicated by the file extension of `path`, defaulting to TOML.
+    fn parse<T: DeserializeOwned>(path: &Path, contents: &str) -> Result<T> {
+        Ok(match path.extension().and_then(OsStr::to_str) {
+            Some("yaml" | "yml") => serde_yaml::from_str(contents)?,
+            Some("json") => serde_json::from_str(contents)?,
+            _ => toml::from_str(contents)?,
+        })
+    }
+    /// Merge this configuration on top of the one it extends, if any, where `path` is the canonical path
+    /// of this configuration's file.
+    fn resolve_extends(self, path: &Path) -> Result<Self> {
+        let Some(base_path) = &self.extends else {
+            return Ok(self);
+        };
+        if EXTENDS_CHAIN.with_borrow(|chain| chain.iter().any(|p| p == path)) {
+            return Err(Error::CircularExtends(path.to_owned()).into());
+        }
+        EXTENDS_CHAIN.with_borrow_mut(|chain| chain.push(path.to_owned()));
+        let base = Self::from_path(
+            path.parent()
+                .expect("path cannot be filesystem root")
+                .join(base_path),
+            Ok,
+        );
+        EXTENDS_CHAIN.with_borrow_mut(|chain| chain.pop());
+        let base = base?;
+        // The base is inlined, so the merged configuration no longer extends it.
+        Ok(Self {
+            extends: None,
+            vars: self.vars,
+            definitions: base.definitions.merge(self.definitions),
+            source: self.source.or(base.source),
+            transforms: base.transforms.into_iter().chain(self.transforms).collect(),
+            exports: base.exports.into_iter().chain(self.exports).collect(),
+            state: self.state.or(base.state),
+            run: self.run,
+        })
+    }
+}
+
+/// Replace `${name}` placeholders in `contents` with the value of the variable, and `$${` with `${`.
+fn substitute_vars(contents: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let pattern = Regex::new(r"\$(\$)?\{([A-Za-z_][A-Za-z0-9_]*)\}").expect("pattern is valid");
+    let mut error = None;
+    let substituted = pattern.replace_all(contents, |captures: &Captures| {
+        let name = &captures[2];
+        if captures.get(1).is_some() {
+            return format!("${{{name}}}");
+        }
+        let value = VAR_OVERRIDES
+            .with_borrow(|overrides| overrides.get(name).cloned())
+            .or_else(|| vars.get(name).cloned())
+            .or_else(|| std::env::var(name).ok());
+        value.unwrap_or_else(|| {
+            error.get_or_insert(Error::UnknownVariable(name.to_owned()));
+            String::new()
+        })
+    });
+    match error {
+        Some(error) => Err(error.into()),
+        None => Ok(substituted.into_owned()),
     }
 }
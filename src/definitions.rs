@@ -0,0 +1,102 @@
+//! Named loaders and transform lists that can be declared once and referenced throughout a configuration.
+
+use crate::{
+    sources::Loader,
+    transforms::{Transform, TransformItem},
+    utils::Error,
+};
+use anyhow::{Context as _, Result};
+use polars::lazy::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::BTreeMap};
+
+thread_local! {
+    /// The definitions of the configuration currently being loaded, if any.
+    static DEFINITIONS: RefCell<Option<Definitions>> = const { RefCell::new(None) };
+    /// Names of the definitions currently being resolved, used to detect cycles.
+    static RESOLVING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Named definitions that can be referenced elsewhere in the configuration.
+///
+/// ```toml
+/// [definitions.loaders.customers]
+/// data.csv.path = "customers.csv"
+///
+/// [[transforms]]
+/// join = { right.data.ref = "customers", how = "left", left_on = [{ expr.col = "customer_id" }], right_on = [{ expr.col = "id" }] }
+/// ```
+#[derive(Clone, Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct Definitions {
+    /// Loaders that can be referenced as a data source with `ref`.
+    #[serde(default)]
+    pub loaders: BTreeMap<String, Loader>,
+    /// Lists of transforms that can be applied in place with a `ref` transform.
+    #[serde(default)]
+    pub transforms: BTreeMap<String, Vec<TransformItem>>,
+}
+
+impl Definitions {
+    /// Add the definitions from `other`, replacing any with the same name.
+    pub(crate) fn merge(mut self, other: Self) -> Self {
+        self.loaders.extend(other.loaders);
+        self.transforms.extend(other.transforms);
+        self
+    }
+
+    /// Run `func` with these definitions available to be referenced.
+    pub(crate) fn scope<T, F>(&self, func: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let previous = DEFINITIONS.replace(Some(self.clone()));
+        let result = func();
+        DEFINITIONS.set(previous);
+        result
+    }
+}
+
+/// Run `func` on the named definition while guarding against it referencing itself.
+fn resolve<T, R, D, F>(kind: &str, name: &str, get: D, func: F) -> Result<R>
+where
+    T: Clone,
+    D: for<'a> FnOnce(&'a Definitions) -> Option<&'a T>,
+    F: FnOnce(T) -> Result<R>,
+{
+    let key = format!("{kind}.{name}");
+    let definition = DEFINITIONS
+        .with_borrow(|definitions| definitions.as_ref().and_then(get).cloned())
+        .ok_or_else(|| Error::UnknownDefinition(key.clone()))?;
+    if RESOLVING.with_borrow(|stack| stack.contains(&key)) {
+        return Err(Error::CircularDefinition(key).into());
+    }
+    RESOLVING.with_borrow_mut(|stack| stack.push(key.clone()));
+    let result = func(definition).with_context(|| format!("definitions.{key}"));
+    RESOLVING.with_borrow_mut(|stack| stack.pop());
+    result
+}
+
+/// Load the named loader definition.
+pub(crate) fn load(name: &str) -> Result<LazyFrame> {
+    resolve(
+        "loaders",
+        name,
+        |definitions| definitions.loaders.get(name),
+        |loader| loader.load(),
+    )
+}
+
+/// Apply the named list of transforms to `lf`.
+pub(crate) fn transform(name: &str, lf: LazyFrame) -> Result<LazyFrame> {
+    resolve(
+        "transforms",
+        name,
+        |definitions| definitions.transforms.get(name),
+        |transforms| {
+            transforms.iter().enumerate().try_fold(lf, |lf, (i, t)| {
+                t.transform(lf).with_context(|| format!("transforms[{i}]"))
+            })
+        },
+    )
+}
@@ -1,22 +1,38 @@
 //! Available methods for exporting data.
 
-use anyhow::Result;
+use crate::utils::{Error, Secret};
+use anyhow::{Context as _, Result};
 use polars::{
     io::SerWriter,
     lazy::prelude::*,
-    prelude::{CsvWriter, PlPath},
+    prelude::{AnyValue, CsvWriter, DataType, JsonFormat, JsonWriter, PlPath, SerializeOptions},
 };
+use regex::Regex;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
+    ffi::OsString,
     fmt::{Debug, Write},
-    path::PathBuf,
+    io::{BufWriter, Write as _},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
+use tracing::info;
 
 /// Trait for a data structure that represents a data export destination.
 pub trait Export: Serialize + for<'a> Deserialize<'a> + JsonSchema + Debug {
     /// Export the supplied data to the specified destination.
-    fn export(&self, lf: LazyFrame) -> Result<()>;
+    fn export(&self, lf: LazyFrame) -> Result<ExportReport>;
+}
+
+/// Details of the output of a single export.
+#[derive(Clone, Debug, Default)]
+pub struct ExportReport {
+    /// Path of the file that was written, if the export wrote a file.
+    pub path: Option<PathBuf>,
+    /// Number of rows written, if known without evaluating the data again.
+    pub rows: Option<usize>,
 }
 
 /// Available exports that can be used in configuration files.
@@ -27,19 +43,285 @@ pub enum ExportItem {
     Csv(CsvExport),
     /// Export data to newline-delimited JSON.
     NdJson(NdJsonExport),
-    /// Collect and serialize the dataframe itself to a single JSON object. You probably don't need this.
+    /// Collect the data and write it to a single JSON document.
     Json(JsonExport),
     /// Export data to Parquet.
     Parquet(ParquetExport),
+    /// Export data to a database table.
+    Sql(SqlExport),
+    /// Export data to an Excel workbook.
+    Excel(ExcelExport),
 }
 
 impl ExportItem {
-    pub(crate) fn export(&self, lf: LazyFrame) -> Result<()> {
+    pub(crate) fn export(&self, lf: LazyFrame) -> Result<ExportReport> {
         match self {
             Self::Csv(export) => export.export(lf),
             Self::NdJson(export) => export.export(lf),
             Self::Json(export) => export.export(lf),
             Self::Parquet(export) => export.export(lf),
+            Self::Sql(export) => export.export(lf),
+            Self::Excel(export) => export.export(lf),
+        }
+    }
+}
+
+/// Timezone used when appending the current time to export filenames.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Timezone {
+    /// The local timezone of the machine running the export.
+    #[default]
+    Local,
+    /// Coordinated Universal Time.
+    Utc,
+    /// A named IANA timezone, e.g. `America/New_York`.
+    Named(chrono_tz::Tz),
+}
+
+impl Timezone {
+    /// The current time in this timezone.
+    pub fn now(&self) -> chrono::NaiveDateTime {
+        match self {
+            Self::Local => chrono::Local::now().naive_local(),
+            Self::Utc => chrono::Utc::now().naive_utc(),
+            Self::Named(tz) => chrono::Utc::now().with_timezone(tz).naive_local(),
+        }
+    }
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        Ok(match value.as_str() {
+            "local" => Self::Local,
+            "utc" => Self::Utc,
+            name => Self::Named(chrono_tz::Tz::from_str(name)?),
+        })
+    }
+}
+
+impl From<Timezone> for String {
+    fn from(value: Timezone) -> Self {
+        match value {
+            Timezone::Local => "local".to_owned(),
+            Timezone::Utc => "utc".to_owned(),
+            Timezone::Named(tz) => tz.name().to_owned(),
+        }
+    }
+}
+
+impl JsonSchema for Timezone {
+    fn schema_name() -> String {
+        String::schema_name()
+    }
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+}
+
+/// Build an export filename from its name, an optional format string for the current time, and an extension.
+/// Placeholders in the name like `{region}` are replaced with the single distinct value of that column,
+/// which requires evaluating the data once per placeholder.
+pub(crate) fn filename(
+    lf: &LazyFrame,
+    name: &str,
+    date_format: Option<&str>,
+    timezone: &Timezone,
+    extension: &str,
+) -> Result<String> {
+    let mut filename = String::new();
+    let mut last = 0;
+    for captures in Regex::new(r"\{([^{}]+)\}")?.captures_iter(name) {
+        let (placeholder, column) = (captures.get(0).unwrap(), &captures[1]);
+        let values = lf
+            .clone()
+            .select([col(column).unique().cast(DataType::String)])
+            .collect()?;
+        let value = match values.height() {
+            1 => values.column(column)?.str()?.get(0),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            Error::Other(format!(
+                "export name placeholder {{{column}}} requires exactly one non-null value, found {}",
+                values.height()
+            ))
+        })?;
+        filename.write_str(&name[last..placeholder.start()])?;
+        filename.write_str(value)?;
+        last = placeholder.end();
+    }
+    filename.write_str(&name[last..])?;
+    if let Some(fstring) = date_format {
+        filename.write_str(&timezone.now().format(fstring).to_string())?
+    }
+    filename.write_str(".")?;
+    filename.write_str(extension)?;
+    Ok(filename)
+}
+
+/// What to do when an export's output file already exists.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IfExists {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+    /// Fail with an error.
+    Error,
+    /// Leave the existing file in place and skip the export.
+    Skip,
+}
+
+impl IfExists {
+    /// Whether an export to `path` should proceed, erroring if the policy forbids it.
+    pub(crate) fn proceed(&self, path: &Path) -> Result<bool> {
+        if !path.exists() {
+            return Ok(true);
+        }
+        match self {
+            Self::Overwrite => Ok(true),
+            Self::Error => Err(Error::ExportExists(path.to_owned()).into()),
+            Self::Skip => Ok(false),
+        }
+    }
+}
+
+/// Description of an exported file, written alongside it so that downstream consumers can tell it is complete.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    /// Absolute path of the exported file.
+    pub path: PathBuf,
+    /// Number of rows in the exported file.
+    pub rows: usize,
+    /// Names and datatypes of the exported columns, in order.
+    #[serde(with = "tuple_vec_map")]
+    pub schema: Vec<(String, String)>,
+    /// Size of the exported file in bytes.
+    pub bytes: u64,
+    /// When the manifest was written, in RFC 3339 format.
+    pub created_at: String,
+}
+
+impl ExportReport {
+    /// Write a manifest describing the exported file to `<filename>.manifest.json` in the same folder,
+    /// e.g. `export.csv.manifest.json`,
+    /// if the export wrote a file. `lf` must be the data that was exported,
+    /// and is evaluated again to count its rows if the export did not report them.
+    pub(crate) fn write_manifest(&self, lf: &LazyFrame) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let rows = match self.rows {
+            Some(rows) => rows,
+            None => lf
+                .clone()
+                .select([len()])
+                .collect()?
+                .column("len")?
+                .idx()?
+                .get(0)
+                .unwrap_or_default() as usize,
+        };
+        let manifest = Manifest {
+            path: path.canonicalize()?,
+            rows,
+            schema: lf
+                .clone()
+                .collect_schema()?
+                .iter()
+                .map(|(name, dtype)| (name.to_string(), dtype.to_string()))
+                .collect(),
+            bytes: std::fs::metadata(path)?.len(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        let mut manifest_name = path
+            .file_name()
+            .context("export path must have a filename")?
+            .to_owned();
+        manifest_name.push(".manifest.json");
+        let manifest_path = path.with_file_name(manifest_name);
+        write_atomic(&manifest_path, |temp| {
+            serde_json::to_writer_pretty(std::fs::File::create(temp)?, &manifest)?;
+            Ok(())
+        })
+    }
+}
+
+/// Write a file by calling `write` with a temporary path in the same folder as `path`,
+/// then atomically renaming it into place if successful, so consumers never see a partially written file.
+/// The temporary file is removed if writing fails.
+pub(crate) fn write_atomic<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    let mut temp_name = OsString::from(".");
+    temp_name.push(
+        path.file_name()
+            .context("export path must have a filename")?,
+    );
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    match write(&temp_path) {
+        Ok(()) => Ok(std::fs::rename(&temp_path, path)?),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Compression applied to exported files.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Write files uncompressed.
+    #[default]
+    None,
+    /// Compress files with gzip, appending `.gz` to the extension.
+    Gzip,
+    /// Compress files with zstd, appending `.zst` to the extension.
+    Zstd,
+}
+
+impl Compression {
+    /// The extension of a file with the given base extension, compressed with this method.
+    fn extension(self, base: &str) -> String {
+        match self {
+            Self::None => base.to_owned(),
+            Self::Gzip => format!("{base}.gz"),
+            Self::Zstd => format!("{base}.zst"),
+        }
+    }
+
+    /// Create a file at `path` and compress everything `write` writes to it.
+    fn write<F>(self, path: &Path, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut dyn std::io::Write) -> Result<()>,
+    {
+        let file = std::fs::File::create(path)?;
+        match self {
+            Self::None => {
+                let mut writer = std::io::BufWriter::new(file);
+                write(&mut writer)?;
+                std::io::Write::flush(&mut writer)?;
+                Ok(())
+            }
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(file, flate2::Compression::default());
+                write(&mut encoder)?;
+                encoder.finish()?;
+                Ok(())
+            }
+            Self::Zstd => {
+                let mut encoder = zstd::Encoder::new(file, 0)?;
+                write(&mut encoder)?;
+                encoder.finish()?;
+                Ok(())
+            }
         }
     }
 }
@@ -50,48 +332,112 @@ pub struct CsvExport {
     /// Folder in which to create files.
     pub folder: PathBuf,
     /// Name of the output file, not including the file extension.
+    /// May contain placeholders like `{region}`, which are filled with the single distinct value of that column.
     pub name: String,
     /// Optional format string to append the current time to the filename -
     /// refer to <https://docs.rs/chrono/latest/chrono/format/strftime/index.html> for available format codes.
     pub date_format: Option<String>,
+    /// Timezone of the time appended to the filename: `local` (the default), `utc`, or a named timezone
+    /// such as `America/New_York`.
+    #[serde(default)]
+    pub timezone: Timezone,
+    /// What to do if the output file already exists.
+    #[serde(default)]
+    pub if_exists: IfExists,
     /// Whether to lazily sink data to the CSV. Defaults to true. Set to false if necessary to resolve errors.
     /// If set to false, all data will be loaded into memory as a [`polars::prelude::DataFrame`] before being
     /// written to disk.
     pub sink: Option<bool>,
+    /// Compression to apply to the output. Compressed output cannot be sunk lazily,
+    /// so all data is loaded into memory before being written if this is set.
+    #[serde(default)]
+    pub compression: Compression,
+    /// Number of decimal places to write floats with. Defaults to as many as needed to represent each value.
+    pub float_precision: Option<usize>,
+    /// Whether to write floats in scientific notation (`true`) or positional notation (`false`).
+    /// Defaults to choosing whichever is shorter for each value.
+    pub float_scientific: Option<bool>,
+    /// `strftime` format to write datetime values in, e.g. `%Y-%m-%dT%H:%M:%SZ`.
+    /// Unlike `date_format`, this applies to the data rather than the filename.
+    pub datetime_format: Option<String>,
+    /// `strftime` format to write date values in, e.g. `%d/%m/%Y`.
+    pub date_column_format: Option<String>,
+    /// Number of rows to serialize at a time. Larger batches write faster but use more memory.
+    pub batch_size: Option<NonZeroUsize>,
+}
+
+impl CsvExport {
+    /// Options controlling how values are written when sinking lazily.
+    fn serialize_options(&self) -> SerializeOptions {
+        SerializeOptions {
+            float_precision: self.float_precision,
+            float_scientific: self.float_scientific,
+            datetime_format: self.datetime_format.clone(),
+            date_format: self.date_column_format.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Create a writer for collected data, with the same options as used when sinking lazily.
+    fn writer<W: std::io::Write>(&self, writer: W) -> CsvWriter<W> {
+        let writer = CsvWriter::new(writer)
+            .include_header(true)
+            .with_separator(b',')
+            .with_float_precision(self.float_precision)
+            .with_float_scientific(self.float_scientific)
+            .with_datetime_format(self.datetime_format.clone())
+            .with_date_format(self.date_column_format.clone());
+        match self.batch_size {
+            Some(batch_size) => writer.with_batch_size(batch_size),
+            None => writer,
+        }
+    }
 }
 
 impl Export for CsvExport {
-    fn export(&self, lf: LazyFrame) -> Result<()> {
+    fn export(&self, lf: LazyFrame) -> Result<ExportReport> {
         std::fs::create_dir_all(&self.folder)?;
-        let mut filename = String::new();
-        filename.write_str(&self.name)?;
-        if let Some(fstring) = &self.date_format {
-            filename.write_str(
-                &chrono::Local::now()
-                    .naive_local()
-                    .format(&fstring)
-                    .to_string(),
-            )?
-        }
-        filename.write_str(".csv")?;
-        if self.sink.unwrap_or(true) {
-            lf.sink_csv(
-                SinkTarget::Path(PlPath::Local(self.folder.join(filename).into())),
-                CsvWriterOptions {
-                    ..Default::default()
-                },
-                None,
-                Default::default(),
-            )?
-            .collect()?;
-        } else {
-            let mut file = std::fs::File::create(self.folder.join(filename))?;
-            CsvWriter::new(&mut file)
-                .include_header(true)
-                .with_separator(b',')
-                .finish(&mut lf.collect()?)?;
+        let filename = filename(
+            &lf,
+            &self.name,
+            self.date_format.as_deref(),
+            &self.timezone,
+            &self.compression.extension("csv"),
+        )?;
+        let path = self.folder.join(filename);
+        if !self.if_exists.proceed(&path)? {
+            return Ok(ExportReport::default());
         }
-        Ok(())
+        write_atomic(&path, |path| {
+            if let Compression::Gzip | Compression::Zstd = self.compression {
+                self.compression.write(path, |writer| {
+                    self.writer(writer).finish(&mut lf.collect()?)?;
+                    Ok(())
+                })?;
+            } else if self.sink.unwrap_or(true) {
+                lf.sink_csv(
+                    SinkTarget::Path(PlPath::Local(path.into())),
+                    CsvWriterOptions {
+                        serialize_options: self.serialize_options(),
+                        batch_size: self
+                            .batch_size
+                            .unwrap_or(CsvWriterOptions::default().batch_size),
+                        ..Default::default()
+                    },
+                    None,
+                    Default::default(),
+                )?
+                .collect()?;
+            } else {
+                let mut file = std::fs::File::create(path)?;
+                self.writer(&mut file).finish(&mut lf.collect()?)?;
+            }
+            Ok(())
+        })?;
+        Ok(ExportReport {
+            path: Some(path),
+            rows: None,
+        })
     }
 }
 
@@ -101,68 +447,256 @@ pub struct NdJsonExport {
     /// Folder in which to create files.
     pub folder: PathBuf,
     /// Name of the output file, not including the file extension.
+    /// May contain placeholders like `{region}`, which are filled with the single distinct value of that column.
     pub name: String,
     /// Optional format string to append the current time to the filename -
     /// refer to <https://docs.rs/chrono/latest/chrono/format/strftime/index.html> for available format codes.
     pub date_format: Option<String>,
+    /// Timezone of the time appended to the filename: `local` (the default), `utc`, or a named timezone
+    /// such as `America/New_York`.
+    #[serde(default)]
+    pub timezone: Timezone,
+    /// What to do if the output file already exists.
+    #[serde(default)]
+    pub if_exists: IfExists,
+    /// Compression to apply to the output. Compressed output cannot be sunk lazily,
+    /// so all data is loaded into memory before being written if this is set.
+    #[serde(default)]
+    pub compression: Compression,
 }
 
 impl Export for NdJsonExport {
-    fn export(&self, lf: LazyFrame) -> Result<()> {
+    fn export(&self, lf: LazyFrame) -> Result<ExportReport> {
         std::fs::create_dir_all(&self.folder)?;
-        let mut filename = String::new();
-        filename.write_str(&self.name)?;
-        if let Some(fstring) = &self.date_format {
-            filename.write_str(
-                &chrono::Local::now()
-                    .naive_local()
-                    .format(&fstring)
-                    .to_string(),
-            )?
+        let filename = filename(
+            &lf,
+            &self.name,
+            self.date_format.as_deref(),
+            &self.timezone,
+            &self.compression.extension("jsonl"),
+        )?;
+        let path = self.folder.join(filename);
+        if !self.if_exists.proceed(&path)? {
+            return Ok(ExportReport::default());
         }
-        filename.write_str(".jsonl")?;
-        lf.sink_json(
-            SinkTarget::Path(PlPath::Local(self.folder.join(filename).into())),
-            JsonWriterOptions::default(),
-            None,
-            Default::default(),
-        )?
-        .collect()?;
-        Ok(())
+        write_atomic(&path, |path| {
+            if let Compression::Gzip | Compression::Zstd = self.compression {
+                self.compression.write(path, |writer| {
+                    JsonWriter::new(writer)
+                        .with_json_format(JsonFormat::JsonLines)
+                        .finish(&mut lf.collect()?)?;
+                    Ok(())
+                })?;
+            } else {
+                lf.sink_json(
+                    SinkTarget::Path(PlPath::Local(path.into())),
+                    JsonWriterOptions::default(),
+                    None,
+                    Default::default(),
+                )?
+                .collect()?;
+            }
+            Ok(())
+        })?;
+        Ok(ExportReport {
+            path: Some(path),
+            rows: None,
+        })
     }
 }
 
-/// Export data to CSV.
+/// Collect the data and write it to a single JSON document, either row- or column-oriented.
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct JsonExport {
     /// Folder in which to create files.
     pub folder: PathBuf,
     /// Name of the output file, not including the file extension.
+    /// May contain placeholders like `{region}`, which are filled with the single distinct value of that column.
     pub name: String,
     /// Optional format string to append the current time to the filename -
     /// refer to <https://docs.rs/chrono/latest/chrono/format/strftime/index.html> for available format codes.
     pub date_format: Option<String>,
+    /// Timezone of the time appended to the filename: `local` (the default), `utc`, or a named timezone
+    /// such as `America/New_York`.
+    #[serde(default)]
+    pub timezone: Timezone,
+    /// What to do if the output file already exists.
+    #[serde(default)]
+    pub if_exists: IfExists,
+    /// Whether to indent the output for readability.
+    #[serde(default)]
+    pub pretty: bool,
+    /// Layout of the output.
+    #[serde(default)]
+    pub orient: JsonOrient,
 }
 
 impl Export for JsonExport {
-    fn export(&self, lf: LazyFrame) -> Result<()> {
+    fn export(&self, lf: LazyFrame) -> Result<ExportReport> {
         std::fs::create_dir_all(&self.folder)?;
-        let mut filename = String::new();
-        filename.write_str(&self.name)?;
-        if let Some(fstring) = &self.date_format {
-            filename.write_str(
-                &chrono::Local::now()
-                    .naive_local()
-                    .format(&fstring)
-                    .to_string(),
-            )?
+        let filename = filename(
+            &lf,
+            &self.name,
+            self.date_format.as_deref(),
+            &self.timezone,
+            "json",
+        )?;
+        let path = self.folder.join(filename);
+        if !self.if_exists.proceed(&path)? {
+            return Ok(ExportReport::default());
+        }
+        let mut df = match self.orient {
+            JsonOrient::Records => lf.collect()?,
+            // Imploding every column gives a single row of lists, which is written as `[{"a": [1, 2]}]`.
+            JsonOrient::Columns => lf.select([Expr::from(all()).implode()]).collect()?,
+        };
+        write_atomic(&path, |path| {
+            let file = BufWriter::new(std::fs::File::create(path)?);
+            let mut writer = JsonLayout::new(
+                file,
+                self.pretty,
+                matches!(self.orient, JsonOrient::Columns),
+            );
+            JsonWriter::new(&mut writer)
+                .with_json_format(JsonFormat::Json)
+                .finish(&mut df)?;
+            writer.finish()?.flush()?;
+            Ok(())
+        })?;
+        Ok(ExportReport {
+            path: Some(path),
+            rows: None,
+        })
+    }
+}
+
+/// Writer that lays out the compact JSON written by polars as it passes through,
+/// so the output never has to be parsed or held in memory.
+struct JsonLayout<W: std::io::Write> {
+    inner: W,
+    /// Whether to indent the output for readability.
+    pretty: bool,
+    /// Whether to drop the array around the records, leaving the single record inside it.
+    unwrap: bool,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    /// Whether a container was just opened, so an immediate close can stay on the same line.
+    opened: bool,
+    written: bool,
+}
+
+impl<W: std::io::Write> JsonLayout<W> {
+    fn new(inner: W, pretty: bool, unwrap: bool) -> Self {
+        Self {
+            inner,
+            pretty,
+            unwrap,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            opened: false,
+            written: false,
+        }
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.written = true;
+        self.inner.write_all(bytes)
+    }
+
+    fn newline(&mut self) -> std::io::Result<()> {
+        if self.pretty {
+            let indent = self.depth.saturating_sub(self.unwrap as usize);
+            self.emit(b"\n")?;
+            for _ in 0..indent {
+                self.emit(b"  ")?;
+            }
         }
-        filename.write_str(".json")?;
-        let file = std::fs::File::create(self.folder.join(filename))?;
-        let df = lf.collect()?;
-        serde_json::to_writer(file, &df)?;
         Ok(())
     }
+
+    fn byte(&mut self, byte: u8) -> std::io::Result<()> {
+        if self.in_string {
+            match byte {
+                _ if self.escaped => self.escaped = false,
+                b'\\' => self.escaped = true,
+                b'"' => self.in_string = false,
+                _ => {}
+            }
+            return self.emit(&[byte]);
+        }
+        if byte.is_ascii_whitespace() {
+            return Ok(());
+        }
+        if std::mem::take(&mut self.opened) {
+            if let b'}' | b']' = byte {
+                self.depth -= 1;
+                return self.emit(&[byte]);
+            }
+            self.newline()?;
+        }
+        match byte {
+            b'{' | b'[' => {
+                self.depth += 1;
+                if self.unwrap && self.depth == 1 {
+                    return Ok(());
+                }
+                self.opened = true;
+                self.emit(&[byte])
+            }
+            b'}' | b']' => {
+                self.depth = self.depth.saturating_sub(1);
+                if self.unwrap && self.depth == 0 {
+                    return Ok(());
+                }
+                self.newline()?;
+                self.emit(&[byte])
+            }
+            b',' => {
+                self.emit(&[byte])?;
+                self.newline()
+            }
+            b':' if self.pretty => self.emit(b": "),
+            b'"' => {
+                self.in_string = true;
+                self.emit(&[byte])
+            }
+            _ => self.emit(&[byte]),
+        }
+    }
+
+    /// Finish the output, returning the inner writer.
+    fn finish(mut self) -> std::io::Result<W> {
+        if self.unwrap && !self.written {
+            self.emit(b"{}")?;
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for JsonLayout<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            self.byte(byte)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Layouts in which a dataframe can be written as JSON.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonOrient {
+    /// An array with an object for each row, e.g. `[{"a": 1}, {"a": 2}]`.
+    #[default]
+    Records,
+    /// An object with an array of values for each column, e.g. `{"a": [1, 2]}`.
+    Columns,
 }
 
 /// Export data to Parquet.
@@ -171,33 +705,284 @@ pub struct ParquetExport {
     /// Folder in which to create files.
     pub folder: PathBuf,
     /// Name of the output file, not including the file extension.
+    /// May contain placeholders like `{region}`, which are filled with the single distinct value of that column.
     pub name: String,
     /// Optional format string to append the current time to the filename -
     /// refer to <https://docs.rs/chrono/latest/chrono/format/strftime/index.html> for available format codes.
     pub date_format: Option<String>,
+    /// Timezone of the time appended to the filename: `local` (the default), `utc`, or a named timezone
+    /// such as `America/New_York`.
+    #[serde(default)]
+    pub timezone: Timezone,
+    /// What to do if the output file already exists.
+    #[serde(default)]
+    pub if_exists: IfExists,
+    /// Maximum number of rows in each row group. Larger row groups compress better and suit readers that
+    /// scan whole files, while smaller ones let readers skip more data. Defaults to the polars default.
+    pub row_group_size: Option<usize>,
 }
 
 impl Export for ParquetExport {
-    fn export(&self, lf: LazyFrame) -> Result<()> {
+    fn export(&self, lf: LazyFrame) -> Result<ExportReport> {
         std::fs::create_dir_all(&self.folder)?;
-        let mut filename = String::new();
-        filename.write_str(&self.name)?;
-        if let Some(fstring) = &self.date_format {
-            filename.write_str(
-                &chrono::Local::now()
-                    .naive_local()
-                    .format(&fstring)
-                    .to_string(),
+        let filename = filename(
+            &lf,
+            &self.name,
+            self.date_format.as_deref(),
+            &self.timezone,
+            "parquet",
+        )?;
+        let path = self.folder.join(filename);
+        if !self.if_exists.proceed(&path)? {
+            return Ok(ExportReport::default());
+        }
+        write_atomic(&path, |path| {
+            lf.sink_parquet(
+                SinkTarget::Path(PlPath::Local(path.into())),
+                ParquetWriteOptions {
+                    row_group_size: self.row_group_size,
+                    ..Default::default()
+                },
+                None,
+                SinkOptions::default(),
             )?
+            .collect()?;
+            Ok(())
+        })?;
+        Ok(ExportReport {
+            path: Some(path),
+            rows: None,
+        })
+    }
+}
+
+/// Export data to a sheet in an Excel (`.xlsx`) workbook.
+/// The data is collected into memory before being written.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ExcelExport {
+    /// Folder in which to create files.
+    pub folder: PathBuf,
+    /// Name of the output file, not including the file extension.
+    /// May contain placeholders like `{region}`, which are filled with the single distinct value of that column.
+    pub name: String,
+    /// Optional format string to append the current time to the filename -
+    /// refer to <https://docs.rs/chrono/latest/chrono/format/strftime/index.html> for available format codes.
+    pub date_format: Option<String>,
+    /// Timezone of the time appended to the filename: `local` (the default), `utc`, or a named timezone
+    /// such as `America/New_York`.
+    #[serde(default)]
+    pub timezone: Timezone,
+    /// What to do if the output file already exists.
+    #[serde(default)]
+    pub if_exists: IfExists,
+    /// Name of the worksheet to write to. Defaults to `Sheet1`.
+    pub sheet_name: Option<String>,
+    /// Whether to write the column names as a header row. Defaults to true.
+    pub header: Option<bool>,
+    /// Whether to widen columns to fit their contents. Defaults to true.
+    pub autofit: Option<bool>,
+}
+
+impl Export for ExcelExport {
+    fn export(&self, lf: LazyFrame) -> Result<ExportReport> {
+        std::fs::create_dir_all(&self.folder)?;
+        let filename = filename(
+            &lf,
+            &self.name,
+            self.date_format.as_deref(),
+            &self.timezone,
+            "xlsx",
+        )?;
+        let path = self.folder.join(filename);
+        if !self.if_exists.proceed(&path)? {
+            return Ok(ExportReport::default());
         }
-        filename.write_str(".parquet")?;
-        lf.sink_parquet(
-            SinkTarget::Path(PlPath::Local(self.folder.join(filename).into())),
-            ParquetWriteOptions::default(),
-            None,
-            SinkOptions::default(),
-        )?
-        .collect()?;
-        Ok(())
+        let df = lf.collect()?;
+        let mut workbook = rust_xlsxwriter::Workbook::new();
+        let sheet = workbook.add_worksheet();
+        if let Some(name) = &self.sheet_name {
+            sheet.set_name(name)?;
+        }
+        let mut first_row = 0;
+        if self.header.unwrap_or(true) {
+            for (i, column) in df.get_columns().iter().enumerate() {
+                sheet.write_string(0, i.try_into()?, column.name().as_str())?;
+            }
+            first_row = 1;
+        }
+        for (i, column) in df.get_columns().iter().enumerate() {
+            let col: u16 = i.try_into()?;
+            for row in 0..df.height() {
+                let excel_row: u32 = (row + first_row).try_into()?;
+                match column.get(row)? {
+                    AnyValue::Null => {}
+                    AnyValue::Boolean(v) => {
+                        sheet.write_boolean(excel_row, col, v)?;
+                    }
+                    AnyValue::String(v) => {
+                        sheet.write_string(excel_row, col, v)?;
+                    }
+                    AnyValue::StringOwned(v) => {
+                        sheet.write_string(excel_row, col, v.as_str())?;
+                    }
+                    v if v.dtype().is_primitive_numeric() => {
+                        sheet.write_number(
+                            excel_row,
+                            col,
+                            v.extract::<f64>().unwrap_or(f64::NAN),
+                        )?;
+                    }
+                    v => {
+                        sheet.write_string(excel_row, col, v.to_string())?;
+                    }
+                }
+            }
+        }
+        if self.autofit.unwrap_or(true) {
+            sheet.autofit();
+        }
+        write_atomic(&path, |path| {
+            workbook.save(path)?;
+            Ok(())
+        })?;
+        Ok(ExportReport {
+            path: Some(path),
+            rows: Some(df.height()),
+        })
+    }
+}
+
+/// What to do when an export's destination table already exists.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TableIfExists {
+    /// Insert the rows into the existing table, which must have compatible columns.
+    Append,
+    /// Drop the existing table and create it again.
+    Replace,
+    /// Fail with an error.
+    #[default]
+    Fail,
+}
+
+/// Export data to a database table, creating it if it does not exist.
+/// Currently only SQLite is supported.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct SqlExport {
+    /// Connection to the database. For SQLite, this is the path to the database file.
+    pub connection: Secret,
+    /// Name of the table to write to.
+    pub table: String,
+    /// What to do if the table already exists.
+    #[serde(default)]
+    pub if_exists: TableIfExists,
+    /// Number of rows to insert with each statement. Defaults to 10,000, or fewer for wide tables
+    /// to stay within SQLite's limit on the number of values in a statement.
+    /// All of the rows are written in a single transaction regardless.
+    pub batch_size: Option<usize>,
+}
+
+/// Maximum number of values SQLite accepts in a single statement.
+const SQLITE_MAX_VARIABLES: usize = 32_766;
+
+/// Quote an SQL identifier, escaping any quotes within it.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// The SQLite column type to store values of the given datatype in.
+fn sqlite_type(dtype: &DataType) -> &'static str {
+    if dtype.is_bool() || dtype.is_integer() {
+        "INTEGER"
+    } else if dtype.is_float() {
+        "REAL"
+    } else {
+        "TEXT"
+    }
+}
+
+/// Convert a value to one that can be bound to an SQLite statement.
+fn sqlite_value(value: AnyValue) -> rusqlite::types::Value {
+    use rusqlite::types::Value;
+    match value {
+        AnyValue::Null => Value::Null,
+        AnyValue::Boolean(v) => Value::Integer(v.into()),
+        AnyValue::String(v) => Value::Text(v.to_owned()),
+        AnyValue::StringOwned(v) => Value::Text(v.to_string()),
+        v if v.dtype().is_integer() => v.extract::<i64>().map_or(Value::Null, Value::Integer),
+        v if v.dtype().is_float() => v.extract::<f64>().map_or(Value::Null, Value::Real),
+        v => Value::Text(v.to_string()),
+    }
+}
+
+impl Export for SqlExport {
+    fn export(&self, lf: LazyFrame) -> Result<ExportReport> {
+        let df = lf.collect()?;
+        let mut conn = rusqlite::Connection::open(self.connection.resolve()?)?;
+        let table = quote_identifier(&self.table);
+        // Everything is written in a single transaction, so a failed run leaves any existing table untouched.
+        let tx = conn.transaction()?;
+        let exists = tx.query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [&self.table],
+            |row| row.get::<_, i64>(0),
+        )? > 0;
+        if exists {
+            match self.if_exists {
+                TableIfExists::Append => {}
+                TableIfExists::Replace => {
+                    tx.execute(&format!("DROP TABLE {table}"), [])?;
+                }
+                TableIfExists::Fail => {
+                    return Err(Error::Other(format!("table {} already exists", self.table)).into());
+                }
+            }
+        }
+        let columns = df.get_columns();
+        tx.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {table} ({})",
+                columns
+                    .iter()
+                    .map(|c| format!("{} {}", quote_identifier(c.name()), sqlite_type(c.dtype())))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            [],
+        )?;
+        let placeholders = format!("({})", vec!["?"; columns.len()].join(", "));
+        let insert = |rows: usize| {
+            format!(
+                "INSERT INTO {table} ({}) VALUES {}",
+                columns
+                    .iter()
+                    .map(|c| quote_identifier(c.name()))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                vec![placeholders.as_str(); rows].join(", ")
+            )
+        };
+        let batch_size = self
+            .batch_size
+            .unwrap_or(10_000)
+            .min(SQLITE_MAX_VARIABLES / columns.len().max(1))
+            .max(1);
+        for start in (0..df.height()).step_by(batch_size) {
+            let end = (start + batch_size).min(df.height());
+            let mut values = Vec::with_capacity((end - start) * columns.len());
+            for row in start..end {
+                for column in columns {
+                    values.push(sqlite_value(column.get(row)?));
+                }
+            }
+            tx.prepare_cached(&insert(end - start))?
+                .execute(rusqlite::params_from_iter(values))?;
+        }
+        tx.commit()?;
+        info!("wrote {} rows to table {}", df.height(), self.table);
+        Ok(ExportReport {
+            path: None,
+            rows: Some(df.height()),
+        })
     }
 }
@@ -4,10 +4,13 @@ use crate::{
     utils::{DataType, Error},
 };
 use anyhow::{Context, Result};
-use polars::{lazy::prelude::*, prelude::Literal as _};
+use polars::{
+    lazy::prelude::*,
+    prelude::{AnyValue, ClosedWindow, Duration, Literal as _, Series},
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, ops::Deref};
+use std::{fmt::Debug, ops::Deref, str::FromStr};
 
 /// Trait for a data structure that evaluates to a [`Expr`].
 pub trait Expression: Serialize + for<'a> Deserialize<'a> + JsonSchema + Debug {
@@ -21,6 +24,14 @@ pub trait Expression: Serialize + for<'a> Deserialize<'a> + JsonSchema + Debug {
 pub enum ExpressionItem {
     /// Specify a column by name (equivalent to [`col`]).
     Col(Column),
+    /// Specify multiple columns by name (equivalent to [`cols`]), applying any operations to each of them.
+    Cols(Columns),
+    /// Specify a column by position, counting from the end if negative.
+    Nth(i64),
+    /// Specify the first column.
+    FirstCol,
+    /// Specify the last column.
+    LastCol,
     /// Match a regex against a column (equivalent to `col(...).str().contains(...)`).
     Match(Match),
     /// Group 2+ items together in a logical AND statement.
@@ -45,12 +56,24 @@ pub enum ExpressionItem {
     Element,
     /// Create a when/then/otherwise expression.
     Condition(Condition),
+    /// Specify a literal list of values with the given element type.
+    LitList(LitList),
+    /// Repeat a value to produce a constant column.
+    Repeat(Repeat),
+    /// Generate a range of dates or datetimes.
+    DateRange(DateRange),
+    /// Build strings by filling the `{}` placeholders of a template with the values of other expressions.
+    Format(Format),
 }
 
 impl Expression for ExpressionItem {
     fn expr(&self) -> Result<Expr> {
         match self {
             Self::Col(expr) => expr.expr(),
+            Self::Cols(expr) => expr.expr(),
+            Self::Nth(n) => Ok(nth(*n).into()),
+            Self::FirstCol => Ok(first().into()),
+            Self::LastCol => Ok(last().into()),
             Self::Match(expr) => expr.expr(),
             Self::And(expr) => expr.expr(),
             Self::Or(expr) => expr.expr(),
@@ -63,6 +86,10 @@ impl Expression for ExpressionItem {
             Self::ConcatStr(expr) => expr.expr(),
             Self::Element => Ok(col("")),
             Self::Condition(expr) => expr.expr(),
+            Self::LitList(expr) => expr.expr(),
+            Self::Repeat(expr) => expr.expr(),
+            Self::DateRange(expr) => expr.expr(),
+            Self::Format(expr) => expr.expr(),
         }
     }
 }
@@ -75,6 +102,21 @@ pub struct ExpressionChain {
     ops: Vec<OpItem>,
 }
 
+impl ExpressionChain {
+    /// Create a chain from an expression with no operations applied.
+    pub fn new<E: Into<ExpressionItem>>(expr: E) -> Self {
+        Self {
+            expr: expr.into(),
+            ops: Vec::new(),
+        }
+    }
+    /// Apply an operation after any already in the chain.
+    pub fn with_op(mut self, op: OpItem) -> Self {
+        self.ops.push(op);
+        self
+    }
+}
+
 impl Expression for ExpressionChain {
     fn expr(&self) -> Result<Expr> {
         self.ops
@@ -87,12 +129,35 @@ impl Expression for ExpressionChain {
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, JsonSchema)]
 pub struct Column(String);
 
+impl Column {
+    /// Refer to the column with the given name.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self(name.into())
+    }
+}
+
+impl From<Column> for ExpressionItem {
+    fn from(value: Column) -> Self {
+        Self::Col(value)
+    }
+}
+
 impl Expression for Column {
     fn expr(&self) -> Result<Expr> {
         Ok(col(self.0.as_str()))
     }
 }
 
+/// Specify multiple columns by name (equivalent to [`polars::prelude::cols`]).
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, JsonSchema)]
+pub struct Columns(Vec<String>);
+
+impl Expression for Columns {
+    fn expr(&self) -> Result<Expr> {
+        Ok(cols(self.0.iter().map(String::as_str)).into())
+    }
+}
+
 /// Match a column against a regex.
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Match {
@@ -186,6 +251,19 @@ impl Expression for Or {
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, JsonSchema)]
 pub struct Literal(String);
 
+impl Literal {
+    /// A literal string value.
+    pub fn new<S: Into<String>>(value: S) -> Self {
+        Self(value.into())
+    }
+}
+
+impl From<Literal> for ExpressionItem {
+    fn from(value: Literal) -> Self {
+        Self::Lit(value)
+    }
+}
+
 impl Expression for Literal {
     fn expr(&self) -> Result<Expr> {
         Ok(lit(self.0.as_str()))
@@ -211,7 +289,8 @@ impl Expression for AsStruct {
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 pub struct IntRange {
     start: i64,
-    // end:
+    /// End of the range (exclusive). Defaults to the number of rows.
+    end: Option<i64>,
     step: i64,
     dtype: DataType,
 }
@@ -220,7 +299,7 @@ impl Expression for IntRange {
     fn expr(&self) -> Result<Expr> {
         Ok(int_range(
             lit(self.start),
-            len(),
+            self.end.map(lit).unwrap_or_else(len),
             self.step,
             self.dtype.deref().clone(),
         ))
@@ -249,11 +328,22 @@ impl Expression for ConcatStr {
     }
 }
 
-/// Create a when/then/otherwise expression.
+/// Create a when/then/otherwise expression, evaluated row by row.
+/// The predicate can compare columns against each other, e.g. to take the larger of two columns:
+/// ```toml
+/// [[transforms.select]]
+/// expr.condition.when = { expr.col = "a", ops = [{ gt = { expr.col = "b" } }] }
+/// expr.condition.then = { expr.col = "a" }
+/// expr.condition.otherwise = { expr.col = "b" }
+/// ops = [{ alias = "larger" }]
+/// ```
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Condition {
+    /// Predicate that must yield boolean values.
     when: Box<ExpressionChain>,
+    /// Value to use in rows where the predicate is true.
     then: Box<ExpressionChain>,
+    /// Value to use in rows where the predicate is false or null.
     otherwise: Box<ExpressionChain>,
 }
 
@@ -264,3 +354,128 @@ impl Expression for Condition {
             .otherwise(self.otherwise.expr()?))
     }
 }
+
+/// Convert a scalar value written in the configuration, such as a number or string, to an [`AnyValue`].
+pub(crate) fn any_value(value: &serde_json::Value) -> Result<AnyValue<'static>> {
+    Ok(match value {
+        serde_json::Value::Null => AnyValue::Null,
+        serde_json::Value::Bool(b) => AnyValue::Boolean(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => AnyValue::Int64(i),
+            None => AnyValue::Float64(n.as_f64().context("invalid number")?),
+        },
+        serde_json::Value::String(s) => AnyValue::StringOwned(s.into()),
+        _ => return Err(Error::Other(format!("expected a scalar value, got {value}")).into()),
+    })
+}
+
+/// Specify a literal list of values, e.g. for checking membership with [`crate::ops::OpItem::IsIn`].
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct LitList {
+    /// The values in the list.
+    values: Vec<serde_json::Value>,
+    /// Data type of the list elements.
+    dtype: DataType,
+}
+
+impl Expression for LitList {
+    fn expr(&self) -> Result<Expr> {
+        let values = self
+            .values
+            .iter()
+            .map(any_value)
+            .collect::<Result<Vec<AnyValue>>>()?;
+        // Build the series from the values as written, then cast, so that e.g. integers can be given for a float list.
+        let series =
+            Series::from_any_values("".into(), &values, false)?.strict_cast(&self.dtype)?;
+        Ok(lit(series).implode())
+    }
+}
+
+/// Repeat a value to produce a constant column.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Repeat {
+    /// The value to repeat.
+    value: Box<ExpressionChain>,
+    /// Number of times to repeat the value. Defaults to the number of rows.
+    n: Option<Box<ExpressionChain>>,
+}
+
+impl Expression for Repeat {
+    fn expr(&self) -> Result<Expr> {
+        Ok(repeat(
+            self.value.expr()?,
+            match &self.n {
+                Some(n) => n.expr()?,
+                None => len(),
+            },
+        ))
+    }
+}
+
+/// Generate a range of dates or datetimes, inclusive of both ends.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct DateRange {
+    /// First value of the range, as a date (`2024-01-01`) or datetime (`2024-01-01T00:00:00`).
+    start: String,
+    /// Last value of the range, as a date or datetime.
+    end: String,
+    /// Interval between values, as a duration string (e.g. `1d`, `1h`).
+    interval: String,
+}
+
+impl DateRange {
+    fn parse(value: &str) -> Result<Expr> {
+        if let Ok(date) = chrono::NaiveDate::from_str(value) {
+            Ok(lit(date))
+        } else {
+            Ok(lit(chrono::NaiveDateTime::from_str(value).with_context(
+                || format!("{value} is not a valid date or datetime"),
+            )?))
+        }
+    }
+}
+
+impl Expression for DateRange {
+    fn expr(&self) -> Result<Expr> {
+        Ok(date_range(
+            Self::parse(&self.start)?,
+            Self::parse(&self.end)?,
+            Duration::try_parse(&self.interval)
+                .map_err(|_| Error::Other(format!("{} is not a valid interval", self.interval)))?,
+            ClosedWindow::Both,
+        ))
+    }
+}
+
+/// Build strings by filling the `{}` placeholders of a template with the values of other expressions, in order.
+///
+/// ```toml
+/// expr.format = { template = "{}/{}", args = [{ expr.col = "region" }, { expr.col = "id" }] }
+/// ```
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Format {
+    /// Template containing one `{}` placeholder for each argument.
+    template: String,
+    /// Expressions whose values fill the placeholders.
+    args: Vec<ExpressionChain>,
+}
+
+impl Expression for Format {
+    fn expr(&self) -> Result<Expr> {
+        let placeholders = self.template.matches("{}").count();
+        if placeholders != self.args.len() {
+            return Err(Error::Other(format!(
+                "format template has {placeholders} placeholders but {} args were given",
+                self.args.len()
+            ))
+            .into());
+        }
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.expr())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(format_str(&self.template, args)?)
+    }
+}
@@ -2,14 +2,16 @@
 
 extern crate tuple_vec_map;
 mod config;
+pub mod definitions;
 pub mod exports;
 pub mod expressions;
 pub mod ops;
 pub mod sources;
+pub mod state;
 pub mod transforms;
 pub mod utils;
 
-pub use config::Config;
+pub use config::{Config, Optimizations, RunOptions};
 
 #[cfg(test)]
 mod tests;
@@ -1,21 +1,69 @@
 //! CLI to run commands relating to `retl` configuration files.
 //! The `dump-schema` subcommand can be used to dump a JSON schema to disk using [`schemars`].
 
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{mpsc::RecvTimeoutError, Arc, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use notify::{Event, RecursiveMode, Watcher};
 use retl::{
-    sources::{Schema, DataSource},
+    sources::{DataSource, Schema},
+    utils::Error,
     Config,
 };
 use schemars::schema_for;
-use tracing::debug;
+use tracing::{debug, error, info, level_filters::LevelFilter};
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
-enum Cli {
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Minimum level or filter directives for log messages, e.g. `debug` or `retl=trace`.
+    /// Falls back to the `RUST_LOG` environment variable, then `info`.
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+    /// Format of log messages.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Full)]
+    log_format: LogFormat,
+}
+
+/// Formats in which log messages can be written.
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    /// Single-line, human-readable messages.
+    Full,
+    /// Shorter single-line messages.
+    Compact,
+    /// Multi-line messages, for reading interactively.
+    Pretty,
+    /// Newline-delimited JSON objects, for log aggregation.
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
     /// Load and run the configuration at the given path.
     Run(RunArgs),
+    /// Load the configuration at the given path and print a preview of the transformed data, without exporting.
+    Inspect {
+        /// Path to the configuration file to inspect.
+        config: PathBuf,
+        /// Number of rows to preview.
+        #[arg(long, default_value_t = 10)]
+        rows: u32,
+    },
+    /// Print the names and types of the columns the configuration at the given path would produce, without reading data.
+    Schema {
+        /// Path to the configuration file.
+        config: PathBuf,
+    },
     /// Dump the configuration JSON schema to the given path.
     DumpSchema {
         /// Path to dump the JSON schema to.
@@ -29,53 +77,258 @@ struct RunArgs {
     config: PathBuf,
     #[arg(long)]
     dump_schema: Option<PathBuf>,
+    /// Print the query plan instead of running the configuration.
+    #[arg(long)]
+    explain: bool,
+    /// Whether the plan printed by `--explain` should be optimized.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    explain_optimized: bool,
+    /// Re-run the configuration whenever the file changes.
+    #[arg(long)]
+    watch: bool,
+    /// Ignore previously processed files recorded in the configuration's state and load everything.
+    #[arg(long)]
+    full_refresh: bool,
+    /// Time each stage of the configuration and log a summary, as if `run.profile` were set.
+    #[arg(long)]
+    profile: bool,
+    /// Print the first N rows of the transformed data before exporting it.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    preview: u32,
+    /// Fail if the run takes longer than the given duration, e.g. `90s`, `30m` or `2h`.
+    /// Polars cannot interrupt a query that is executing, so a timed-out run is abandoned rather than stopped,
+    /// and a streaming export in progress keeps writing to its temporary file until the process exits.
+    /// With `--watch`, changes made while an abandoned run is still executing are not re-run.
+    #[arg(long, value_parser = parse_duration)]
+    timeout: Option<Duration>,
+    /// Print the configuration as TOML after merging any configurations it extends, instead of running it.
+    #[arg(long)]
+    print_config: bool,
+    /// Set a variable for `${name}` placeholders in the configuration, overriding its `vars` and the environment.
+    /// May be given more than once.
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    vars: Vec<(String, String)>,
 }
+
+/// Parse a variable given as `NAME=VALUE`.
+fn parse_var(value: &str) -> Result<(String, String)> {
+    let (name, value) = value
+        .split_once('=')
+        .ok_or_else(|| Error::Other(format!("expected NAME=VALUE, got {value:?}")))?;
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+/// Parse a duration given as a whole number of seconds, optionally followed by a unit of `s`, `m` or `h`.
+fn parse_duration(value: &str) -> Result<Duration> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1),
+        Some((i, 'm')) => (&value[..i], 60),
+        Some((i, 'h')) => (&value[..i], 60 * 60),
+        _ => (value, 1),
+    };
+    Ok(Duration::from_secs(number.parse::<u64>()? * multiplier))
+}
+
+/// How long to wait for file events to settle before re-running in watch mode.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
 fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-    match Cli::parse() {
-        Cli::Run(args) => Config::from_path(&args.config.canonicalize()?, |config| {
-            debug!("Running parsed config: {:?}", config);
-            if let Some(path) = &args.dump_schema {
-                let schema = config.load()?.collect_schema()?.as_ref().clone();
-                let mut writer = std::fs::File::create(path)?;
-                let mut source = config.source.clone();
-                match &mut source.data {
-                    DataSource::Csv(source) => {
-                        source.schema = Some(Schema(schema));
-                    }
-                    DataSource::Json(source) => {
-                        source.schema = Some(Schema(schema));
-                    }
-                    DataSource::JsonLine(source) => {
-                        source.schema = Some(Schema(schema));
-                    }
-                    DataSource::Parquet(source) => {
-                        source.schema = Some(Schema(schema));
-                    }
-                    _ => {
-                        writer.write(toml::to_string_pretty(&schema)?.as_bytes())?;
-                        writer.flush()?;
-                        return Ok(());
-                    }
-                }
-                writer.write(
-                    toml::to_string_pretty(&Config {
-                        source,
-                        exports: Default::default(),
-                        transforms: Default::default(),
-                    })?
-                    .as_bytes(),
-                )?;
-                writer.flush()?;
-                Ok(())
+    let cli = Cli::parse();
+    init_logging(cli.log_level.as_deref(), cli.log_format)?;
+    match cli.command {
+        Command::Run(args) => {
+            let path = args.config.canonicalize()?;
+            let args = Arc::new(args);
+            let run_once = || {
+                let (path, args) = (path.clone(), args.clone());
+                with_timeout(args.timeout, move || {
+                    Config::with_vars(args.vars.iter().cloned().collect(), || {
+                        Config::from_path(&path, |config| run(&args, config))
+                    })
+                })
+            };
+            if args.watch {
+                watch(&path, run_once)
             } else {
-                config.run()
+                run_once()
             }
+        }
+        Command::Inspect { config, rows } => Config::from_path(&config.canonicalize()?, |config| {
+            let df = config.load()?.limit(rows).collect()?;
+            println!("Schema:");
+            for (name, dtype) in df.schema().iter() {
+                println!("  {name}: {dtype}");
+            }
+            println!("{df}");
+            Ok(())
+        }),
+        Command::Schema { config } => Config::from_path(&config.canonicalize()?, |config| {
+            for (name, dtype) in config.schema()?.iter() {
+                println!("{name}: {dtype}");
+            }
+            Ok(())
         }),
-        Cli::DumpSchema { path } => {
+        Command::DumpSchema { path } => {
             let schema = schema_for!(Config);
             let writer = std::fs::File::create(path)?;
             Ok(serde_json::to_writer_pretty(writer, &schema)?)
         }
     }
 }
+
+/// The thread of the last run that timed out, which may still be executing.
+/// Runs change the process's working directory, so no other run may start until it finishes.
+static ABANDONED: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+/// Run `func` on a separate thread, failing with [`Error::Timeout`] if it does not finish within `timeout`.
+/// Fails with [`Error::TimedOutRunActive`] without running `func` if a previous run that timed out is still executing.
+fn with_timeout<F>(timeout: Option<Duration>, func: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    let mut abandoned = ABANDONED.lock().expect("lock is not poisoned");
+    if abandoned
+        .as_ref()
+        .is_some_and(|handle| !handle.is_finished())
+    {
+        return Err(Error::TimedOutRunActive.into());
+    }
+    let Some(timeout) = timeout else {
+        return func();
+    };
+    let (tx, rx) = std::sync::mpsc::channel();
+    let handle = std::thread::spawn(move || {
+        // The receiver is gone if the run timed out, in which case the result is discarded.
+        let _ = tx.send(func());
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            *abandoned = Some(handle);
+            Err(Error::Timeout(timeout).into())
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(Error::Other("run panicked".to_owned()).into()),
+    }
+}
+
+/// Install a global subscriber writing log messages at `level` or above in the given format.
+fn init_logging(level: Option<&str>, format: LogFormat) -> Result<()> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .from_env_lossy(),
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Full => builder.init(),
+        LogFormat::Compact => builder.compact().init(),
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Json => builder.json().init(),
+    }
+    Ok(())
+}
+
+/// Run a parsed configuration according to the given arguments.
+fn run(args: &RunArgs, mut config: Config) -> Result<()> {
+    if let Some(state) = config.state.as_mut() {
+        state.full_refresh |= args.full_refresh;
+    }
+    config.run.profile |= args.profile;
+    debug!("Running parsed config: {:?}", config);
+    if args.print_config {
+        print!("{}", toml::to_string_pretty(&config)?);
+        Ok(())
+    } else if args.explain {
+        println!("{}", config.load()?.explain(args.explain_optimized)?);
+        Ok(())
+    } else if let Some(path) = &args.dump_schema {
+        let schema = config.schema()?;
+        let mut writer = std::fs::File::create(path)?;
+        let mut source = config.source.clone().ok_or(Error::NoSource)?;
+        match &mut source.data {
+            DataSource::Csv(source) => {
+                source.schema = Some(Schema(schema));
+            }
+            DataSource::Json(source) => {
+                source.schema = Some(Schema(schema));
+            }
+            DataSource::JsonLine(source) => {
+                source.schema = Some(Schema(schema));
+            }
+            DataSource::Parquet(source) => {
+                source.schema = Some(Schema(schema));
+            }
+            _ => {
+                writer.write(toml::to_string_pretty(&schema)?.as_bytes())?;
+                writer.flush()?;
+                return Ok(());
+            }
+        }
+        writer.write(
+            toml::to_string_pretty(&Config {
+                extends: None,
+                vars: Default::default(),
+                definitions: Default::default(),
+                source: Some(source),
+                exports: Default::default(),
+                transforms: Default::default(),
+                state: None,
+                run: Default::default(),
+            })?
+            .as_bytes(),
+        )?;
+        writer.flush()?;
+        Ok(())
+    } else {
+        // Preview the same plan that is exported, so that it reflects the files selected by any state
+        // and the seeds drawn by random operations.
+        config.run_with(|lf| {
+            if args.preview > 0 {
+                println!("{}", lf.clone().limit(args.preview).collect()?);
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Run `func` once, then again whenever the file at `path` changes, until interrupted.
+fn watch<F>(path: &Path, func: F) -> Result<()>
+where
+    F: Fn() -> Result<()>,
+{
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    // Editors often save by writing a new file and renaming it over the old one,
+    // which would orphan a watch on the file itself, so watch its directory instead.
+    watcher.watch(
+        path.parent().expect("path cannot be filesystem root"),
+        RecursiveMode::NonRecursive,
+    )?;
+    if let Err(e) = func() {
+        error!("{e:?}");
+    }
+    loop {
+        let event: Event = rx.recv()??;
+        if !event.kind.is_modify() && !event.kind.is_create() {
+            continue;
+        }
+        let Some(trigger) = event
+            .paths
+            .iter()
+            .find(|p| p.file_name() == path.file_name())
+        else {
+            continue;
+        };
+        // Wait for a single save's burst of events to settle before re-running.
+        while rx.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+        info!(
+            "{} changed at {}, re-running",
+            trigger.display(),
+            chrono::Local::now().format("%F %T")
+        );
+        if let Err(e) = func() {
+            error!("{e:?}");
+        }
+    }
+}
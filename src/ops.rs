@@ -1,13 +1,54 @@
 //! Operations that can be used to modify/compose [`Expr`]s.
 use crate::{
     expressions::{Expression, ExpressionChain},
-    utils::DataType,
+    utils::{DataType, Error},
 };
 use anyhow::Result;
+use chrono::format::{Item, StrftimeItems};
 use polars::{lazy::prelude::*, prelude::*};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt::Debug, ops::Deref};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt::Debug,
+    hash::{BuildHasher, RandomState},
+    ops::Deref,
+};
+
+thread_local! {
+    /// State from which random operations draw their seeds, while a configuration with `run.seed` is loaded.
+    static SEED: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Run `func` with random operations drawing their seeds from `seed`, so that they are reproducible.
+/// Outside of this, each random operation draws a seed from the system's entropy instead.
+pub(crate) fn with_seed<T, F>(seed: u64, func: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    let previous = SEED.replace(Some(seed));
+    let result = func();
+    SEED.set(previous);
+    result
+}
+
+/// Draw a seed for a random operation as it is added to the plan, so that every evaluation of the plan,
+/// such as by each of several exports, produces the same result.
+fn next_seed() -> u64 {
+    match SEED.get() {
+        Some(state) => {
+            // SplitMix64, which gives well-distributed seeds even for similar `run.seed` values.
+            let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            SEED.set(Some(state));
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        }
+        None => RandomState::new().hash_one(()),
+    }
+}
 
 /// Trait for an operation that modifies an expression supplied as input.
 pub trait Op: Serialize + for<'a> Deserialize<'a> + JsonSchema + Debug {
@@ -63,13 +104,81 @@ pub enum OpItem {
     Cast(Cast),
     /// Apply a `struct`-namespaced operation.
     Struct(Struct),
-    // Dt(Dt),
+    /// Apply a `dt`-namespaced operation.
+    Dt(Dt),
     /// Sort elements of a series.
     Sort(SortOptions),
     /// Evaluate to the first element of a series.
     First,
     /// Map values of an expression using a hashmap lookup. Any values not found will map to null.
     Map(Map),
+    /// Compute the exponentially weighted moving average.
+    EwmMean(EwmMean),
+    /// Bin values into buckets based on quantiles.
+    QCut(QCut),
+    /// Check if values are NaN.
+    IsNan(IsNan),
+    /// Fill in NaN values in a column with an expression.
+    FillNan(FillNan),
+    /// Check if values are contained in a list, such as one given by [`crate::expressions::LitList`].
+    IsIn(ExpressionChain),
+    /// Count the number of unique values, including null.
+    NUnique,
+    /// Count the number of distinct values, including null. Alias of [`OpItem::NUnique`].
+    CountDistinct,
+    /// Evaluate to the last element of a series.
+    Last,
+    /// Compute the median of a series.
+    Median,
+    /// Compute the standard deviation of a series.
+    Std(Std),
+    /// Compute the variance of a series.
+    Var(Var),
+    /// Compute the sum of a series.
+    Sum,
+    /// Compute the mean of a series.
+    Mean,
+    /// Compute the minimum of a series.
+    Min,
+    /// Compute the maximum of a series.
+    Max,
+    /// Compute a quantile of a series, such as the 95th percentile.
+    Quantile(Quantile),
+    /// Take the first N elements of a series. Within a group by aggregation, this collects them into a list per group.
+    Head(usize),
+    /// Take the last N elements of a series. Within a group by aggregation, this collects them into a list per group.
+    Tail(usize),
+    /// Compute a 64-bit hash of each value, e.g. to pseudonymize identifiers or assign rows to buckets.
+    Hash(Hash),
+    /// Evaluate to the index of the minimum value of a series.
+    ArgMin,
+    /// Evaluate to the index of the maximum value of a series, e.g. to find which row in each group has the highest score.
+    ArgMax,
+    /// Select elements by index, counting from the end if negative.
+    /// Combined with [`OpItem::ArgMax`] inside a group by, this can select other fields from the row with the highest value.
+    Gather(ExpressionChain),
+    /// Check if each value is the first occurrence of that value.
+    IsFirstDistinct,
+    /// Check if each value is the last occurrence of that value.
+    IsLastDistinct,
+    /// Check if each value occurs more than once.
+    IsDuplicated,
+    /// Count the number of null values.
+    NullCount,
+    /// Randomly reorder values. The order is the same for every export of a run.
+    /// Set `run.seed` for it to be the same between runs too.
+    Shuffle,
+    /// Check whether all values of a boolean series are true.
+    All(All),
+    /// Check whether any value of a boolean series is true.
+    Any(Any),
+    /// Remove NaN values in a float column. Unlike `drop_null`, null values are kept.
+    DropNan,
+    /// Divide by another series, giving null (or a fallback) rather than infinity or NaN where it is zero.
+    SafeDiv(SafeDiv),
+    /// Keep only the distinct values of a series. In a `group_by` aggregation, this collects the distinct values
+    /// of each group into a list.
+    Unique(Unique),
 }
 
 impl OpItem {
@@ -97,9 +206,41 @@ impl OpItem {
             Self::Sub(op) => op.apply(expr),
             Self::Cast(op) => op.apply(expr),
             Self::Struct(op) => op.apply(expr),
+            Self::Dt(op) => op.apply(expr),
             Self::Sort(op) => Ok(expr.sort(op.clone())),
             Self::First => Ok(expr.first()),
             Self::Map(op) => op.apply(expr),
+            Self::EwmMean(op) => op.apply(expr),
+            Self::QCut(op) => op.apply(expr),
+            Self::IsNan(op) => op.apply(expr),
+            Self::FillNan(op) => op.apply(expr),
+            Self::IsIn(other) => Ok(expr.is_in(other.expr()?, false)),
+            Self::NUnique | Self::CountDistinct => Ok(expr.n_unique()),
+            Self::Last => Ok(expr.last()),
+            Self::Median => Ok(expr.median()),
+            Self::Std(op) => op.apply(expr),
+            Self::Var(op) => op.apply(expr),
+            Self::Sum => Ok(expr.sum()),
+            Self::Mean => Ok(expr.mean()),
+            Self::Min => Ok(expr.min()),
+            Self::Max => Ok(expr.max()),
+            Self::Quantile(op) => op.apply(expr),
+            Self::Head(n) => Ok(expr.head(Some(*n))),
+            Self::Tail(n) => Ok(expr.tail(Some(*n))),
+            Self::Hash(op) => op.apply(expr),
+            Self::ArgMin => Ok(expr.arg_min()),
+            Self::ArgMax => Ok(expr.arg_max()),
+            Self::Gather(indices) => Ok(expr.gather(indices.expr()?)),
+            Self::IsFirstDistinct => Ok(expr.is_first_distinct()),
+            Self::IsLastDistinct => Ok(expr.is_last_distinct()),
+            Self::IsDuplicated => Ok(expr.is_duplicated()),
+            Self::NullCount => Ok(expr.null_count()),
+            Self::Shuffle => Ok(expr.shuffle(Some(next_seed()))),
+            Self::All(op) => op.apply(expr),
+            Self::Any(op) => op.apply(expr),
+            Self::DropNan => Ok(expr.drop_nans()),
+            Self::SafeDiv(op) => op.apply(expr),
+            Self::Unique(op) => op.apply(expr),
         }
     }
 }
@@ -359,6 +500,33 @@ impl Op for Div {
     }
 }
 
+/// Divide the expression by another, giving a fallback value where the divisor is zero.
+///
+/// ```toml
+/// ops = [{ safe_div = { by = { expr.col = "orders" }, fallback = { expr.lit = "0", ops = [{ cast = "Float64" }] } } }]
+/// ```
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct SafeDiv {
+    /// The divisor.
+    pub by: ExpressionChain,
+    /// Value to use where the divisor is zero. Defaults to null.
+    pub fallback: Option<ExpressionChain>,
+}
+
+impl Op for SafeDiv {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        let by = self.by.expr()?;
+        let fallback = match &self.fallback {
+            Some(fallback) => fallback.expr()?,
+            None => NULL.lit(),
+        };
+        // The division is the `then` branch so that the result keeps the name of the dividend.
+        Ok(when(by.clone().neq(lit(0)).fill_null(lit(true)))
+            .then(expr / by)
+            .otherwise(fallback))
+    }
+}
+
 /// Multiply the expression by another.
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Mul(ExpressionChain);
@@ -406,14 +574,90 @@ pub enum Struct {
     JsonEncode,
     /// Extract a single field by name from a struct column.
     Field(String),
+    /// Extract a single field by its position from a struct column, for structs whose field names are unknown or unstable.
+    /// Fails when the query runs if the struct has fewer fields.
+    FieldByIndex(usize),
+    /// Keep only the named fields of a struct column, in the given order, e.g. to select a few fields before unnesting.
+    Fields(Vec<String>),
 }
 
 impl Op for Struct {
     fn apply(&self, expr: Expr) -> Result<Expr> {
-        let ns = expr.struct_();
+        let ns = expr.clone().struct_();
         Ok(match self {
             Self::JsonEncode => ns.json_encode(),
             Self::Field(name) => ns.field_by_name(name),
+            Self::FieldByIndex(index) => {
+                ns.field_by_index(i64::try_from(*index).map_err(|_| {
+                    Error::Other(format!("struct field index {index} is too large"))
+                })?)
+            }
+            Self::Fields(names) => {
+                if names.is_empty() {
+                    return Err(Error::Other("no struct fields given".to_owned()).into());
+                }
+                as_struct(
+                    names
+                        .iter()
+                        .map(|name| expr.clone().struct_().field_by_name(name))
+                        .collect(),
+                )
+                .name()
+                .keep()
+            }
+        })
+    }
+}
+
+/// Apply a `dt`-namespaced operation.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Dt {
+    /// Convert a timezone-aware datetime column to another timezone, e.g. `Europe/London`.
+    /// The instant in time is unchanged and only the local time it is displayed as changes.
+    ConvertTimeZone(String),
+    /// Set the timezone of a datetime column without converting it, so the local time is unchanged
+    /// and is reinterpreted as being in the new timezone. This can be used to localize naive datetimes,
+    /// or with a null timezone (in JSON or YAML) to make a timezone-aware column naive again.
+    ReplaceTimeZone(Option<String>),
+    /// Format a date or datetime column as strings using a `strftime` format, e.g. `%Y-%m` for month buckets.
+    Strftime(String),
+    /// Shift dates or datetimes by a calendar-aware duration string, e.g. `1mo`, `-2d` or `3bd` (business days).
+    /// Unlike adding a fixed duration, this respects month and year boundaries, so `2024-01-31` offset by `1mo`
+    /// is `2024-02-29`.
+    OffsetBy(String),
+    /// Round dates or datetimes down to the start of an interval given as a duration string, e.g. `1d` or `1h`.
+    /// Useful as a `group_by` key for time-bucketed aggregations.
+    Truncate(String),
+}
+
+impl Op for Dt {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        let ns = expr.dt();
+        Ok(match self {
+            Self::ConvertTimeZone(tz) => ns.convert_time_zone(
+                TimeZone::opt_try_new(Some(tz.as_str()))?
+                    .ok_or_else(|| Error::Other(format!("invalid timezone: {tz:?}")))?,
+            ),
+            Self::ReplaceTimeZone(tz) => ns.replace_time_zone(
+                TimeZone::opt_try_new(tz.as_deref())?,
+                lit("raise"),
+                NonExistent::Raise,
+            ),
+            Self::Strftime(format) => {
+                if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                    return Err(Error::Other(format!("invalid strftime format: {format:?}")).into());
+                }
+                ns.strftime(format)
+            }
+            Self::OffsetBy(by) => {
+                Duration::try_parse(by)?;
+                ns.offset_by(lit(by.as_str()))
+            }
+            Self::Truncate(every) => {
+                Duration::try_parse(every)?;
+                ns.truncate(lit(every.as_str()))
+            }
         })
     }
 }
@@ -458,3 +702,231 @@ impl Op for Map {
         // todo!()
     }
 }
+
+/// Compute the exponentially weighted moving average. Exactly one of `alpha` or `span` must be provided.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct EwmMean {
+    /// Smoothing factor, where `0 < alpha <= 1`.
+    pub alpha: Option<f64>,
+    /// Decay in terms of span, where `alpha = 2 / (span + 1)`.
+    pub span: Option<f64>,
+    /// Whether to divide by a decaying adjustment factor in the beginning periods.
+    #[serde(default)]
+    pub adjust: bool,
+}
+
+impl Op for EwmMean {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        let alpha = match (self.alpha, self.span) {
+            (Some(alpha), None) => alpha,
+            (None, Some(span)) => 2.0 / (span + 1.0),
+            _ => {
+                return Err(Error::Other(
+                    "ewm_mean requires exactly one of alpha or span".to_owned(),
+                )
+                .into())
+            }
+        };
+        Ok(expr.ewm_mean(EWMOptions {
+            alpha,
+            adjust: self.adjust,
+            ..Default::default()
+        }))
+    }
+}
+
+/// Bin values into categorical buckets based on quantiles.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct QCut {
+    /// Quantiles to break buckets at, between 0 and 1.
+    pub quantiles: Vec<f64>,
+    /// Names of the buckets. Must contain exactly one more label than there are quantiles.
+    pub labels: Option<Vec<String>>,
+    /// Whether to merge buckets whose edges are duplicates rather than raising an error.
+    #[serde(default)]
+    pub allow_duplicates: bool,
+}
+
+impl Op for QCut {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        if let Some(labels) = &self.labels {
+            if labels.len() != self.quantiles.len() + 1 {
+                return Err(Error::Other(format!(
+                    "q_cut requires {} labels for {} quantiles, got {}",
+                    self.quantiles.len() + 1,
+                    self.quantiles.len(),
+                    labels.len()
+                ))
+                .into());
+            }
+        }
+        Ok(expr.qcut(
+            self.quantiles.clone(),
+            self.labels.clone(),
+            false,
+            self.allow_duplicates,
+            false,
+        ))
+    }
+}
+
+/// Check if values are NaN. Unlike [`IsNull`], null values remain null.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct IsNan(bool);
+
+impl Op for IsNan {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        Ok(if self.0 {
+            expr.is_nan()
+        } else {
+            expr.is_not_nan()
+        })
+    }
+}
+
+/// Fill in NaN values with a given expression. Null values are left untouched.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct FillNan(ExpressionChain);
+
+impl Op for FillNan {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        Ok(expr.fill_nan(self.0.expr()?))
+    }
+}
+
+/// Compute the standard deviation of a series.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Std {
+    /// Delta degrees of freedom, subtracted from the number of values in the divisor. Defaults to 1.
+    pub ddof: Option<u8>,
+}
+
+impl Op for Std {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        Ok(expr.std(self.ddof.unwrap_or(1)))
+    }
+}
+
+/// Compute the variance of a series.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Var {
+    /// Delta degrees of freedom, subtracted from the number of values in the divisor. Defaults to 1.
+    pub ddof: Option<u8>,
+}
+
+impl Op for Var {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        Ok(expr.var(self.ddof.unwrap_or(1)))
+    }
+}
+
+/// Methods of interpolating a quantile that falls between two values.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuantileInterpol {
+    /// Use the value closest to the quantile.
+    #[default]
+    Nearest,
+    /// Use the lower of the two values.
+    Lower,
+    /// Use the higher of the two values.
+    Higher,
+    /// Use the mean of the two values.
+    Midpoint,
+    /// Interpolate linearly between the two values.
+    Linear,
+}
+
+impl From<QuantileInterpol> for QuantileMethod {
+    fn from(value: QuantileInterpol) -> Self {
+        match value {
+            QuantileInterpol::Nearest => Self::Nearest,
+            QuantileInterpol::Lower => Self::Lower,
+            QuantileInterpol::Higher => Self::Higher,
+            QuantileInterpol::Midpoint => Self::Midpoint,
+            QuantileInterpol::Linear => Self::Linear,
+        }
+    }
+}
+
+/// Compute a quantile of a series.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Quantile {
+    /// The quantile to compute, between 0 and 1.
+    pub quantile: f64,
+    /// How to interpolate when the quantile falls between two values.
+    #[serde(default)]
+    pub interpolation: QuantileInterpol,
+}
+
+impl Op for Quantile {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        if !(0.0..=1.0).contains(&self.quantile) {
+            return Err(Error::Other(format!(
+                "quantile must be between 0 and 1, got {}",
+                self.quantile
+            ))
+            .into());
+        }
+        Ok(expr.quantile(lit(self.quantile), self.interpolation.into()))
+    }
+}
+
+/// Compute a 64-bit hash of each value.
+/// Hashes are stable for a given seed, but may change between versions of `retl`.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Hash {
+    /// Seed for the hash function. Defaults to 0.
+    pub seed: Option<u64>,
+}
+
+impl Op for Hash {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        let seed = self.seed.unwrap_or(0);
+        Ok(expr.hash(seed, seed, seed, seed))
+    }
+}
+
+/// Check whether all values of a boolean series are true.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct All {
+    /// Skip null values. Otherwise, the result is null if there are nulls and no false values. Defaults to true.
+    pub ignore_nulls: Option<bool>,
+}
+
+impl Op for All {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        Ok(expr.all(self.ignore_nulls.unwrap_or(true)))
+    }
+}
+
+/// Check whether any value of a boolean series is true.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Any {
+    /// Skip null values. Otherwise, the result is null if there are nulls and no true values. Defaults to true.
+    pub ignore_nulls: Option<bool>,
+}
+
+impl Op for Any {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        Ok(expr.any(self.ignore_nulls.unwrap_or(true)))
+    }
+}
+
+/// Keep only the distinct values of a series.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Unique {
+    /// Keep values in the order they first appear, rather than an arbitrary order.
+    #[serde(default)]
+    pub maintain_order: bool,
+}
+
+impl Op for Unique {
+    fn apply(&self, expr: Expr) -> Result<Expr> {
+        Ok(if self.maintain_order {
+            expr.unique_stable()
+        } else {
+            expr.unique()
+        })
+    }
+}
@@ -2,19 +2,25 @@
 
 use crate::{
     config::Config,
+    definitions, state,
     transforms::{Transform, TransformItem},
-    utils::{CanonicalPath, CanonicalPaths},
+    utils::{expand_dirs, CanonicalPath, CanonicalPaths, Error},
 };
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use polars::{
     frame::DataFrame,
     io::SerReader,
     lazy::prelude::*,
-    prelude::{JsonReader, PlPath},
+    prelude::{CsvReader, JsonFormat, JsonReader, PlPath, PlSmallStr, UnionArgs},
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, sync::Arc};
+use std::{cell::RefCell, fmt::Debug, io::Cursor, num::NonZeroUsize, path::PathBuf, sync::Arc};
+
+thread_local! {
+    /// Canonical paths and depth limits of the configuration sources currently being loaded, outermost first.
+    static CONFIG_SOURCES: RefCell<Vec<(PathBuf, usize)>> = const { RefCell::new(Vec::new()) };
+}
 
 /// Trait for a source of data that can be loaded into a [`LazyFrame`].
 pub trait Source: Serialize + for<'a> Deserialize<'a> + JsonSchema + Debug {
@@ -52,6 +58,20 @@ pub enum DataSource {
     ///     values   = [1, 2, 3]
     /// ```
     Inline(InlineSource),
+    /// Parse data written directly in the configuration as CSV, newline-delimited JSON or a JSON array.
+    /// Example:
+    /// ```toml
+    /// [source.data.literal]
+    /// format = "csv"
+    /// data = """
+    /// code,label
+    /// A,Active
+    /// I,Inactive
+    /// """
+    /// ```
+    Literal(LiteralSource),
+    /// Load data using a loader declared by name in the configuration's `definitions`.
+    Ref(String),
 }
 
 impl Source for DataSource {
@@ -63,6 +83,8 @@ impl Source for DataSource {
             Self::Config(source) => source.load(),
             Self::Parquet(source) => source.load(),
             Self::Inline(source) => source.load(),
+            Self::Literal(source) => source.load(),
+            Self::Ref(name) => definitions::load(name),
         }
     }
 }
@@ -78,25 +100,46 @@ pub struct Loader {
 }
 
 impl Loader {
+    /// Create a loader for `data` with no transforms.
+    pub fn new(data: DataSource) -> Self {
+        Self {
+            data,
+            transforms: Vec::new(),
+        }
+    }
+    /// Add a transform to apply to the data after loading it.
+    pub fn with_transform<T: Into<TransformItem>>(mut self, transform: T) -> Self {
+        self.transforms.push(transform.into());
+        self
+    }
     pub(crate) fn load(&self) -> Result<LazyFrame> {
-        let mut lf = self.data.load()?;
-        for transform in self.transforms.iter() {
-            lf = transform.transform(lf)?;
+        let mut lf = self.data.load().context("data")?;
+        for (i, transform) in self.transforms.iter().enumerate() {
+            lf = state::untracked(|| transform.transform(lf))
+                .with_context(|| format!("transforms[{i}]"))?;
         }
         Ok(lf)
     }
 }
 
 /// A valid ASCII CSV separator, represented internally as a [`u8`].
+/// A tab can be given either as a literal tab character or as the escape sequence `\t`.
 #[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
-#[serde(try_from = "char")]
-pub struct Separator(u8);
+#[serde(try_from = "String")]
+pub struct Separator(pub(crate) u8);
 
-impl TryFrom<char> for Separator {
+impl TryFrom<String> for Separator {
     type Error = anyhow::Error;
 
-    fn try_from(value: char) -> std::result::Result<Self, Self::Error> {
-        Ok(Self(u8::try_from(value)?))
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        match value.as_bytes() {
+            [b'\\', b't'] => Ok(Self(b'\t')),
+            [byte] => Ok(Self(*byte)),
+            _ => Err(Error::Other(format!(
+                "separator must be a single ASCII character, got {value:?}"
+            ))
+            .into()),
+        }
     }
 }
 
@@ -115,38 +158,125 @@ impl JsonSchema for Schema {
     }
 }
 
+/// Resolve the name of the column to hold each row's source file path, if one was requested.
+fn file_path_column(name: &Option<String>) -> Option<PlSmallStr> {
+    name.as_deref().map(|name| match name {
+        "" => "file_path".into(),
+        name => name.into(),
+    })
+}
+
+/// How to combine the files matched by a source's paths.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceConcat {
+    /// Scan all files together. Every file must have the same columns.
+    #[default]
+    Vertical,
+    /// Scan each file separately and concatenate them diagonally, filling columns missing from a file with null.
+    /// This tolerates columns being added or removed between files, but schemas are inferred per file
+    /// and files cannot be scanned in a single pass, which can be considerably slower with many files.
+    Diagonal,
+}
+
+impl SourceConcat {
+    /// Scan `paths` using `scan`, either all at once or one file at a time.
+    fn scan<F>(self, paths: Vec<PlPath>, scan: F) -> Result<LazyFrame>
+    where
+        F: Fn(Arc<[PlPath]>) -> Result<LazyFrame>,
+    {
+        match self {
+            Self::Vertical => scan(paths.into()),
+            Self::Diagonal => Ok(concat_lf_diagonal(
+                paths
+                    .into_iter()
+                    .map(|path| scan(Arc::new([path])))
+                    .collect::<Result<Vec<_>>>()?,
+                UnionArgs::default(),
+            )?),
+        }
+    }
+}
+
+/// Handle a source whose path matched no files, either failing or returning an empty frame with the given schema.
+fn no_files(
+    path: &CanonicalPaths,
+    allow_empty: bool,
+    schema: Option<&Schema>,
+) -> Result<LazyFrame> {
+    if !allow_empty {
+        return Err(Error::NoMatchingFiles(path.pattern().to_owned()).into());
+    }
+    let schema = schema.map(|schema| schema.0.clone()).unwrap_or_default();
+    Ok(DataFrame::empty_with_schema(&schema).lazy())
+}
+
 /// Load data from CSV.
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 pub struct CsvSource {
     /// The path to load files from.
     /// This path is passed directly to [`LazyCsvReader`], so paths with globs are permissible
-    /// (e.g. `./files/*.csv`).
+    /// (e.g. `./files/*.csv`). A directory loads every file inside it with the given `extension`.
     pub path: CanonicalPaths,
+    /// Extension of the files to load when `path` is a directory. Defaults to `csv`.
+    pub extension: Option<String>,
+    /// Load no rows, with the columns given by `schema`, if `path` matches no files, rather than failing.
+    #[serde(default)]
+    pub allow_empty: bool,
     /// Separator to use when parsing.
     pub separator: Option<Separator>,
-    /// Whether or not files have headers.
+    /// Whether or not files have headers. Defaults to true, even if a `schema` is given.
     pub has_header: Option<bool>,
     /// Optional [`polars::prelude::Schema`] to enforce specific datatypes.
+    /// If files have headers, this overrides the types of the named columns.
+    /// If `has_header` is false, this must be the full schema, and its column names are used in order.
     pub schema: Option<Schema>,
+    /// Name of a column to add containing the path of the file each row was loaded from.
+    /// Defaults to `file_path` if set to an empty string.
+    pub include_file_path: Option<String>,
+    /// How to combine multiple files matched by `path`.
+    #[serde(default)]
+    pub concat: SourceConcat,
+    /// Whether to combine the loaded data into a single contiguous chunk of memory, which can speed up later
+    /// operations after reading many files at the cost of a copy. Defaults to the polars default.
+    pub rechunk: Option<bool>,
 }
 
 impl Source for CsvSource {
     fn load(&self) -> Result<LazyFrame> {
-        let paths: Arc<[PlPath]> = self
-            .path
-            .iter()
-            .map(|path_buf| PlPath::Local(path_buf.clone().into()))
-            .collect::<Vec<PlPath>>()
-            .into();
-        let mut reader = LazyCsvReader::new_paths(paths);
-        reader = reader.with_has_header(self.has_header.as_ref().unwrap_or(&true).to_owned());
-        if self.separator.is_some() {
-            reader = reader.with_separator(self.separator.as_ref().unwrap().0)
+        let files = expand_dirs(
+            self.path.iter().map(PathBuf::as_path),
+            self.extension.as_deref().unwrap_or("csv"),
+        )?;
+        if files.is_empty() {
+            return no_files(&self.path, self.allow_empty, self.schema.as_ref());
         }
-        reader = reader
-            .with_truncate_ragged_lines(true)
-            .with_dtype_overwrite(self.schema.as_ref().map(|s| Arc::new(s.0.clone())));
-        Ok(reader.finish()?)
+        let paths = state::new_files(files.iter().map(PathBuf::as_path))?
+            .into_iter()
+            .map(|path| PlPath::Local(path.into()))
+            .collect::<Vec<PlPath>>();
+        let has_header = self.has_header.unwrap_or(true);
+        let schema = self.schema.as_ref().map(|s| Arc::new(s.0.clone()));
+        self.concat.scan(paths, |paths| {
+            let mut reader = LazyCsvReader::new_paths(paths);
+            reader = reader.with_has_header(has_header);
+            if self.separator.is_some() {
+                reader = reader.with_separator(self.separator.as_ref().unwrap().0)
+            }
+            reader = if has_header {
+                reader.with_dtype_overwrite(schema.clone())
+            } else {
+                // Without a header there are no names to match overrides against, so the schema is taken as-is.
+                reader.with_schema(schema.clone())
+            };
+            reader = reader
+                .with_truncate_ragged_lines(true)
+                .with_include_file_paths(file_path_column(&self.include_file_path));
+            if let Some(rechunk) = self.rechunk {
+                reader = reader.with_rechunk(rechunk);
+            }
+            Ok(reader.finish()?)
+        })
     }
 }
 
@@ -155,23 +285,58 @@ impl Source for CsvSource {
 pub struct JsonLineSource {
     /// The path to load files from.
     /// This path is passed directly to [`LazyJsonLineReader`], so paths with globs are permissible
-    /// (e.g. `./files/*.csv`).
+    /// (e.g. `./files/*.csv`). A directory loads every file inside it with the given `extension`.
     pub path: CanonicalPaths,
+    /// Extension of the files to load when `path` is a directory. Defaults to `jsonl`.
+    pub extension: Option<String>,
+    /// Load no rows, with the columns given by `schema`, if `path` matches no files, rather than failing.
+    #[serde(default)]
+    pub allow_empty: bool,
     /// Optional [`polars::prelude::Schema`] to enforce specific datatypes.
     pub schema: Option<Schema>,
+    /// Name of a column to add containing the path of the file each row was loaded from.
+    /// Defaults to `file_path` if set to an empty string.
+    pub include_file_path: Option<String>,
+    /// How to combine multiple files matched by `path`.
+    #[serde(default)]
+    pub concat: SourceConcat,
+    /// Number of rows to read when inferring the schema, or 0 to read every row.
+    /// Increase this if fields only appear after the first few rows. Defaults to 100.
+    pub infer_schema_length: Option<usize>,
+    /// Maximum number of rows to read from each scan.
+    pub n_rows: Option<usize>,
+    /// Whether to combine the loaded data into a single contiguous chunk of memory, which can speed up later
+    /// operations after reading many files at the cost of a copy. Defaults to the polars default.
+    pub rechunk: Option<bool>,
 }
 
 impl Source for JsonLineSource {
     fn load(&self) -> Result<LazyFrame> {
-        let paths: Arc<[PlPath]> = self
-            .path
-            .iter()
-            .map(|path_buf| PlPath::Local(path_buf.clone().into()))
-            .collect::<Vec<PlPath>>()
-            .into();
-        let mut reader = LazyJsonLineReader::new_paths(paths);
-        reader = reader.with_schema_overwrite(self.schema.as_ref().map(|s| Arc::new(s.0.clone())));
-        Ok(reader.finish()?)
+        let files = expand_dirs(
+            self.path.iter().map(PathBuf::as_path),
+            self.extension.as_deref().unwrap_or("jsonl"),
+        )?;
+        if files.is_empty() {
+            return no_files(&self.path, self.allow_empty, self.schema.as_ref());
+        }
+        let paths = state::new_files(files.iter().map(PathBuf::as_path))?
+            .into_iter()
+            .map(|path| PlPath::Local(path.into()))
+            .collect::<Vec<PlPath>>();
+        self.concat.scan(paths, |paths| {
+            let mut reader = LazyJsonLineReader::new_paths(paths);
+            reader = reader
+                .with_schema_overwrite(self.schema.as_ref().map(|s| Arc::new(s.0.clone())))
+                .with_include_file_paths(file_path_column(&self.include_file_path))
+                .with_n_rows(self.n_rows);
+            if let Some(length) = self.infer_schema_length {
+                reader = reader.with_infer_schema_length(NonZeroUsize::new(length));
+            }
+            if let Some(rechunk) = self.rechunk {
+                reader = reader.with_rechunk(rechunk);
+            }
+            Ok(reader.finish()?)
+        })
     }
 }
 
@@ -202,11 +367,32 @@ impl Source for JsonSource {
 pub struct ConfigSource {
     /// Path to the configuration file.
     pub path: CanonicalPath,
+    /// Maximum number of configuration sources that may be nested inside one another starting from this one,
+    /// counting this one. Defaults to 16.
+    pub max_depth: Option<usize>,
 }
 
 impl Source for ConfigSource {
     fn load(&self) -> Result<LazyFrame> {
-        Config::from_path(&self.path, |config| config.load())
+        let path = self.path.to_path_buf();
+        let max_depth = self.max_depth.unwrap_or(16);
+        CONFIG_SOURCES.with_borrow(|stack| {
+            if stack.iter().any(|(p, _)| p == &path) {
+                return Err(Error::CircularConfigSource(path.clone()));
+            }
+            // Each source limits how deeply sources may be nested from its own position in the stack.
+            let limits = stack.iter().map(|(_, limit)| *limit).chain([max_depth]);
+            for (i, limit) in limits.enumerate() {
+                if stack.len() - i >= limit {
+                    return Err(Error::ConfigSourceDepth(limit));
+                }
+            }
+            Ok(())
+        })?;
+        CONFIG_SOURCES.with_borrow_mut(|stack| stack.push((path, max_depth)));
+        let result = Config::from_path(&self.path, |config| config.load());
+        CONFIG_SOURCES.with_borrow_mut(|stack| stack.pop());
+        result
     }
 }
 
@@ -214,21 +400,49 @@ impl Source for ConfigSource {
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 pub struct ParquetSource {
     /// Path to the configuration file.
+    /// A local directory loads every file inside it with the given `extension`.
     pub paths: Arc<[PlPath]>,
+    /// Extension of the files to load when a path is a local directory. Defaults to `parquet`.
+    pub extension: Option<String>,
     /// Optional [`polars::prelude::Schema`] to enforce specific datatypes.
     pub schema: Option<Schema>,
+    /// Name of a column to add containing the path of the file each row was loaded from.
+    /// Defaults to `file_path` if set to an empty string.
+    pub include_file_path: Option<String>,
+    /// Whether to combine the loaded data into a single contiguous chunk of memory, which can speed up later
+    /// operations after reading many files at the cost of a copy. Defaults to the polars default.
+    pub rechunk: Option<bool>,
 }
 
 impl Source for ParquetSource {
     fn load(&self) -> Result<LazyFrame> {
+        let extension = self.extension.as_deref().unwrap_or("parquet");
+        let mut expanded = Vec::new();
+        for path in self.paths.iter() {
+            match path.as_local_path() {
+                Some(local) => expanded.extend(
+                    expand_dirs([local], extension)?
+                        .into_iter()
+                        .map(|path| PlPath::Local(path.into())),
+                ),
+                None => expanded.push(path.clone()),
+            }
+        }
+        let new = state::new_files(expanded.iter().filter_map(PlPath::as_local_path))?;
+        let paths: Arc<[PlPath]> = expanded
+            .iter()
+            .filter(|path| path.as_local_path().is_none_or(|path| new.contains(&path)))
+            .cloned()
+            .collect();
         Ok(LazyFrame::scan_parquet_files(
-            self.paths.clone(),
+            paths,
             ScanArgsParquet {
                 schema: self
                     .schema
                     .as_ref()
                     .map(|schema| Arc::new(schema.0.clone())),
-
+                include_file_paths: file_path_column(&self.include_file_path),
+                rechunk: self.rechunk.unwrap_or(ScanArgsParquet::default().rechunk),
                 ..Default::default()
             },
         )?)
@@ -253,6 +467,13 @@ impl Source for ParquetSource {
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct InlineSource(DataFrame);
 
+impl InlineSource {
+    /// Use the given [`DataFrame`] as a source.
+    pub fn new(df: DataFrame) -> Self {
+        Self(df)
+    }
+}
+
 impl Source for InlineSource {
     fn load(&self) -> Result<LazyFrame> {
         Ok(self.0.clone().lazy())
@@ -267,3 +488,40 @@ impl JsonSchema for InlineSource {
         schemars::schema::Schema::Bool(true)
     }
 }
+
+/// Formats in which literal data can be written.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LiteralFormat {
+    /// CSV with a header row.
+    Csv,
+    /// Newline-delimited JSON.
+    Ndjson,
+    /// A JSON array of objects. Nested objects are loaded as struct columns.
+    Json,
+}
+
+/// Parse data written directly in the configuration.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct LiteralSource {
+    /// The format the data is written in.
+    pub format: LiteralFormat,
+    /// The data itself.
+    pub data: String,
+}
+
+impl Source for LiteralSource {
+    fn load(&self) -> Result<LazyFrame> {
+        let cursor = Cursor::new(self.data.as_bytes());
+        let df = match self.format {
+            LiteralFormat::Csv => CsvReader::new(cursor).finish()?,
+            LiteralFormat::Ndjson => JsonReader::new(cursor)
+                .with_json_format(JsonFormat::JsonLines)
+                .finish()?,
+            LiteralFormat::Json => JsonReader::new(cursor)
+                .with_json_format(JsonFormat::Json)
+                .finish()?,
+        };
+        Ok(df.lazy())
+    }
+}
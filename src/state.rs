@@ -0,0 +1,137 @@
+//! Tracking of processed input files, so that repeated runs only load files that are new or changed.
+
+use crate::utils::Error;
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tracing::info;
+
+thread_local! {
+    /// The files seen so far by the run currently being tracked, if any.
+    static TRACKER: RefCell<Option<Tracker>> = const { RefCell::new(None) };
+    /// Whether tracking is suspended, while loading secondary sources such as the right side of a join.
+    static UNTRACKED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Configuration for incremental processing of glob-based sources.
+/// Only the files loaded by the top-level `source` are tracked. Sources loaded by transforms,
+/// such as the right side of a `join` or the sources of a `concat`, always load all of their files.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct StateConfig {
+    /// Path to the file recording which input files have already been processed.
+    pub path: PathBuf,
+    /// Ignore previously recorded files and load everything, recording it all as processed.
+    #[serde(default)]
+    pub full_refresh: bool,
+}
+
+impl StateConfig {
+    /// Run `func` while tracking the input files loaded by sources,
+    /// skipping any that were recorded by a previous run and recording the rest if `func` succeeds.
+    pub(crate) fn track<F>(&self, func: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let previous = if self.full_refresh || !self.path.exists() {
+            State::default()
+        } else {
+            serde_json::from_reader(std::fs::File::open(&self.path)?)?
+        };
+        TRACKER.set(Some(Tracker {
+            previous,
+            seen: State::default(),
+        }));
+        let result = func();
+        let tracker = TRACKER
+            .take()
+            .expect("tracker is set for the duration of the run");
+        match result {
+            Ok(()) => {
+                let mut state = tracker.previous;
+                state.files.extend(tracker.seen.files);
+                serde_json::to_writer_pretty(std::fs::File::create(&self.path)?, &state)?;
+                Ok(())
+            }
+            Err(e) if matches!(e.downcast_ref(), Some(Error::NoNewFiles)) => {
+                info!("no new input files since the last run");
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Input files that have been processed, keyed by path.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct State {
+    files: BTreeMap<PathBuf, FileStamp>,
+}
+
+/// Identifies a particular version of a file.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct FileStamp {
+    modified: SystemTime,
+    size: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            modified: metadata.modified()?,
+            size: metadata.len(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Tracker {
+    previous: State,
+    seen: State,
+}
+
+/// Run `func` without tracking the input files loaded by sources, so that secondary sources
+/// always load all of their files and never cause a run to be skipped.
+pub(crate) fn untracked<T, F>(func: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    let previous = UNTRACKED.replace(true);
+    let result = func();
+    UNTRACKED.set(previous);
+    result
+}
+
+/// Filter out paths that were already processed by a previous run, if tracking is active.
+/// Returns [`Error::NoNewFiles`] if paths were given but all of them were filtered out.
+pub(crate) fn new_files<'a, I>(paths: I) -> Result<Vec<&'a Path>>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    TRACKER.with_borrow_mut(|tracker| {
+        let Some(tracker) = tracker.as_mut().filter(|_| !UNTRACKED.get()) else {
+            return Ok(paths.into_iter().collect());
+        };
+        let mut any = false;
+        let mut new = Vec::new();
+        for path in paths {
+            any = true;
+            let stamp = FileStamp::of(path)?;
+            if tracker.previous.files.get(path) != Some(&stamp) {
+                new.push(path);
+                tracker.seen.files.insert(path.to_owned(), stamp);
+            }
+        }
+        if any && new.is_empty() {
+            Err(Error::NoNewFiles.into())
+        } else {
+            Ok(new)
+        }
+    })
+}
@@ -1,5 +1,2751 @@
 use crate::*;
+use anyhow::Result;
+use polars::prelude::*;
+use sources::{DataSource, Source};
+use std::{path::PathBuf, sync::Mutex};
+use transforms::{Transform, TransformItem};
+
+/// Loading a configuration changes the process-wide current directory, so tests that do so must hold this lock.
+static CURRENT_DIR: Mutex<()> = Mutex::new(());
+
+/// Create an empty scratch directory for a test.
+fn scratch_dir(name: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("retl-{}-{name}", std::process::id()));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Parse a single source from TOML and load its data.
+fn load(source: &str) -> Result<DataFrame> {
+    let source: DataSource = toml::from_str(source)?;
+    Ok(source.load()?.collect()?)
+}
+
+/// Parse a single transform from TOML and apply it to the given data.
+fn apply(df: DataFrame, transform: &str) -> Result<DataFrame> {
+    let transform: TransformItem = toml::from_str(transform)?;
+    Ok(transform.transform(df.lazy())?.collect()?)
+}
+
+#[test]
+fn transpose() -> Result<()> {
+    let df = df!(
+        "a" => [1i64, 2],
+        "b" => [3i64, 4],
+        "c" => [5i64, 6],
+    )?;
+    let out = apply(
+        df,
+        r#"transpose = { header_name = "column", column_names = ["x", "y"] }"#,
+    )?;
+    assert_eq!(out.shape(), (3, 3));
+    assert_eq!(
+        out.column("column")?
+            .str()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        ["a", "b", "c"]
+    );
+    assert_eq!(
+        out.column("y")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [2, 4, 6]
+    );
+    Ok(())
+}
+
+#[test]
+fn map_values() -> Result<()> {
+    let df = df!("status" => ["A", "I", "A", "X"])?;
+    let out = apply(
+        df,
+        r#"
+        [map_values]
+        column = "status"
+        mapping = { A = "Active", I = "Inactive" }
+        default = { expr = { lit = "Unknown" } }
+        "#,
+    )?;
+    assert_eq!(
+        out.column("status")?
+            .str()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        ["Active", "Inactive", "Active", "Unknown"]
+    );
+    Ok(())
+}
+
+#[test]
+fn cut() -> Result<()> {
+    let df = df!("age" => [5.0, 17.0, 30.0, 70.0])?;
+    let out = apply(
+        df,
+        r#"
+        [cut]
+        column = "age"
+        breaks = [17.0, 64.0]
+        labels = ["child", "adult", "senior"]
+        "#,
+    )?;
+    assert_eq!(
+        out.column("age")?
+            .cast(&DataType::String)?
+            .str()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        ["child", "child", "adult", "senior"]
+    );
+    let df = df!("age" => [5.0])?;
+    assert!(apply(
+        df,
+        r#"cut = { column = "age", breaks = [17.0], labels = ["child"] }"#
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn to_dummies() -> Result<()> {
+    let df = df!(
+        "id" => [1i64, 2, 3, 4],
+        "color" => ["red", "green", "blue", "red"],
+    )?;
+    let out = apply(df, r#"to_dummies = { columns = ["color"] }"#)?;
+    let mut names = out.get_column_names_str();
+    names.sort();
+    assert_eq!(names, ["color_blue", "color_green", "color_red", "id"]);
+    assert_eq!(
+        out.column("color_red")?
+            .cast(&DataType::Int64)?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 0, 0, 1]
+    );
+    Ok(())
+}
+
+#[test]
+fn upsample() -> Result<()> {
+    let df = df!(
+        "date" => ["2024-01-01", "2024-01-03", "2024-01-04"],
+        "value" => [1i64, 2, 3],
+    )?
+    .lazy()
+    .with_columns([col("date").str().to_date(StrptimeOptions::default())])
+    .collect()?;
+    let out = apply(
+        df,
+        r#"upsample = { time_column = "date", every = "1d", fill = "forward" }"#,
+    )?;
+    assert_eq!(out.height(), 4);
+    assert_eq!(
+        out.column("value")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 1, 2, 3]
+    );
+    assert!(apply(out, r#"upsample = { time_column = "date", every = "1x" }"#).is_err());
+    Ok(())
+}
+
+#[test]
+fn upsample_by() -> Result<()> {
+    // The dates are only sorted within each group, not overall.
+    let df = df!(
+        "id" => ["a", "a", "b", "b"],
+        "date" => ["2024-01-01", "2024-01-03", "2024-01-02", "2024-01-04"],
+        "value" => [1i64, 3, 2, 4],
+    )?
+    .lazy()
+    .with_columns([col("date").str().to_date(StrptimeOptions::default())])
+    .collect()?;
+    let out = apply(
+        df,
+        r#"upsample = { time_column = "date", every = "1d", by = ["id"], fill = "forward" }"#,
+    )?
+    .sort(["id", "date"], Default::default())?;
+    assert_eq!(
+        out.column("value")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 1, 3, 2, 2, 4]
+    );
+    Ok(())
+}
+
+#[test]
+fn ewm_mean() -> Result<()> {
+    let df = df!("a" => [1.0, 2.0, 3.0])?;
+    let out = apply(
+        df.clone(),
+        r#"select = [{ expr = { col = "a" }, ops = [{ ewm_mean = { alpha = 0.5 } }] }]"#,
+    )?;
+    assert_eq!(
+        out.column("a")?
+            .f64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1.0, 1.5, 2.25]
+    );
+    assert!(apply(
+        df,
+        r#"select = [{ expr = { col = "a" }, ops = [{ ewm_mean = { alpha = 0.5, span = 3.0 } }] }]"#,
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn qcut() -> Result<()> {
+    let df = df!("a" => [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0])?;
+    let out = apply(
+        df,
+        r#"
+        [[select]]
+        expr.col = "a"
+        ops = [
+            { q_cut = { quantiles = [0.25, 0.5, 0.75], labels = ["q1", "q2", "q3", "q4"] } },
+            { cast = "String" },
+        ]
+        "#,
+    )?;
+    assert_eq!(
+        out.column("a")?
+            .str()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        ["q1", "q1", "q2", "q2", "q3", "q3", "q4", "q4"]
+    );
+    let err = apply(
+        df!("a" => [1.0])?,
+        r#"select = [{ expr.col = "a", ops = [{ q_cut = { quantiles = [0.5], labels = ["low"] } }] }]"#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "q_cut requires 2 labels for 1 quantiles, got 1"
+    );
+    Ok(())
+}
+
+#[test]
+fn nan_ops() -> Result<()> {
+    let df = df!("a" => [Some(1.0), Some(f64::NAN), None])?;
+    let out = apply(
+        df,
+        r#"
+        [[select]]
+        expr.col = "a"
+        ops = [{ is_nan = true }, { alias = "is_nan" }]
+
+        [[select]]
+        expr.col = "a"
+        ops = [{ fill_nan = { expr.lit = "0", ops = [{ cast = "Float64" }] } }]
+        "#,
+    )?;
+    assert_eq!(
+        out.column("is_nan")?
+            .bool()?
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [Some(false), Some(true), None]
+    );
+    assert_eq!(
+        out.column("a")?.f64()?.into_iter().collect::<Vec<_>>(),
+        [Some(1.0), Some(0.0), None]
+    );
+    Ok(())
+}
+
+#[test]
+fn config_extends() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("extends")?;
+    std::fs::write(dir.join("data.csv"), "a,b\n1,2\n")?;
+    std::fs::write(
+        dir.join("base.toml"),
+        r#"
+        source.data.csv.path = "data.csv"
+
+        [[transforms]]
+        select = [{ expr.col = "a" }]
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "base"
+        "#,
+    )?;
+    std::fs::create_dir_all(dir.join("child"))?;
+    std::fs::write(
+        dir.join("child/child.toml"),
+        r#"
+        extends = "../base.toml"
+
+        [[transforms]]
+        limit = 1
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "child"
+        "#,
+    )?;
+    let config = Config::from_path(dir.join("child/child.toml"), Ok)?;
+    assert!(config.source.is_some());
+    assert_eq!(config.transforms.len(), 2);
+    assert!(matches!(config.transforms[0], TransformItem::Select(_)));
+    assert_eq!(config.exports.len(), 2);
+    assert!(config.extends.is_none());
+    assert_eq!(config.load()?.collect()?.get_column_names_str(), ["a"]);
+
+    std::fs::write(dir.join("a.toml"), r#"extends = "b.toml""#)?;
+    std::fs::write(dir.join("b.toml"), r#"extends = "a.toml""#)?;
+    let err = Config::from_path(dir.join("a.toml"), Ok).unwrap_err();
+    assert!(err.to_string().contains("circular"));
+    Ok(())
+}
+
+#[test]
+fn config_yaml() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("yaml")?;
+    std::fs::write(dir.join("data.csv"), "a,b\n1,2\n")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        source.data.csv.path = "data.csv"
+
+        [[transforms]]
+        select = [{ expr.col = "a", ops = [{ alias = "c" }] }]
+        "#,
+    )?;
+    std::fs::write(
+        dir.join("config.yaml"),
+        "
+source:
+  data:
+    csv:
+      path: data.csv
+transforms:
+  - select:
+      - expr:
+          col: a
+        ops:
+          - alias: c
+",
+    )?;
+    let toml = Config::from_path(dir.join("config.toml"), Ok)?;
+    let yaml = Config::from_path(dir.join("config.yaml"), Ok)?;
+    assert_eq!(format!("{toml:?}"), format!("{yaml:?}"));
+    assert_eq!(yaml.load()?.collect()?.get_column_names_str(), ["c"]);
+    Ok(())
+}
+
+#[test]
+fn config_json() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("json")?;
+    std::fs::write(dir.join("data.csv"), "a,b\n1,2\n")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        source.data.csv.path = "data.csv"
+
+        [[transforms]]
+        select = [{ expr.col = "b" }]
+        "#,
+    )?;
+    let toml = Config::from_path(dir.join("config.toml"), Ok)?;
+    let transforms = serde_json::to_string(&toml.transforms)?;
+    std::fs::write(
+        dir.join("config.json"),
+        format!(
+            r#"{{"source": {{"data": {{"csv": {{"path": "data.csv"}}}}}}, "transforms": {transforms}}}"#
+        ),
+    )?;
+    let json = Config::from_path(dir.join("config.json"), Ok)?;
+    assert_eq!(format!("{toml:?}"), format!("{json:?}"));
+    assert_eq!(json.load()?.collect()?.get_column_names_str(), ["b"]);
+    Ok(())
+}
+
+#[test]
+fn export_filename_timezone() -> Result<()> {
+    use exports::{filename, Timezone};
+
+    let format = "_%Y-%m-%dT%H";
+    let expected = || format!("export{}.csv", chrono::Utc::now().format(format));
+    // The hour may change while the filename is built, so accept the hour on either side of it.
+    let before = expected();
+    let name = filename(
+        &LazyFrame::default(),
+        "export",
+        Some(format),
+        &Timezone::Utc,
+        "csv",
+    )?;
+    let after = expected();
+    assert!(name == before || name == after, "{name}");
+    assert_eq!(
+        Timezone::try_from("Asia/Tokyo".to_owned())?,
+        Timezone::Named(chrono_tz::Tz::Asia__Tokyo)
+    );
+    assert!(Timezone::try_from("Not/AZone".to_owned()).is_err());
+    Ok(())
+}
+
+#[test]
+fn export_write_atomic() -> Result<()> {
+    use exports::write_atomic;
+    use std::io::Write;
+
+    let dir = scratch_dir("atomic")?;
+    let path = dir.join("export.csv");
+    let err = write_atomic(&path, |temp| {
+        let mut file = std::fs::File::create(temp)?;
+        file.write_all(b"a,b\n1,")?;
+        anyhow::bail!("writer failed")
+    });
+    assert!(err.is_err());
+    assert_eq!(std::fs::read_dir(&dir)?.count(), 0);
+
+    write_atomic(&path, |temp| Ok(std::fs::write(temp, "a,b\n1,2\n")?))?;
+    assert_eq!(std::fs::read_to_string(&path)?, "a,b\n1,2\n");
+    assert_eq!(std::fs::read_dir(&dir)?.count(), 1);
+    Ok(())
+}
+
+#[test]
+fn export_filename_placeholder() -> Result<()> {
+    use exports::{filename, Timezone};
+
+    let lf = df!(
+        "region" => ["EU", "EU"],
+        "sales" => [1i64, 2],
+    )?
+    .lazy();
+    assert_eq!(
+        filename(&lf, "sales_{region}", None, &Timezone::Local, "csv")?,
+        "sales_EU.csv"
+    );
+    let lf = df!("region" => ["EU", "US"])?.lazy();
+    assert!(filename(&lf, "sales_{region}", None, &Timezone::Local, "csv").is_err());
+    Ok(())
+}
+
+#[test]
+fn export_if_exists() -> Result<()> {
+    use exports::ExportItem;
+
+    let dir = scratch_dir("if_exists")?;
+    let path = dir.join("export.csv");
+    let export = |policy: &str| -> Result<()> {
+        let export: ExportItem = toml::from_str(&format!(
+            "type = 'csv'\nfolder = '{}'\nname = 'export'\nif_exists = '{policy}'",
+            dir.display()
+        ))?;
+        export.export(df!("a" => [1i64])?.lazy())?;
+        Ok(())
+    };
+    std::fs::write(&path, "existing\n")?;
+
+    export("skip")?;
+    assert_eq!(std::fs::read_to_string(&path)?, "existing\n");
+    assert!(export("error").is_err());
+    assert_eq!(std::fs::read_to_string(&path)?, "existing\n");
+    export("overwrite")?;
+    assert_eq!(std::fs::read_to_string(&path)?, "a\n1\n");
+    Ok(())
+}
+
+#[test]
+fn collect_profile() -> Result<()> {
+    use transforms::Collect;
+
+    let collect: Collect = toml::from_str("profile = true")?;
+    let (df, timings) =
+        collect.collect(df!("a" => [1i64, 2])?.lazy().select([col("a") * lit(2)]))?;
+    assert_eq!(df.height(), 2);
+    let timings = timings.expect("profiling should produce timings");
+    assert!(timings.height() > 0);
+    assert!(timings.get_column_names_str().contains(&"node"));
+
+    let df = apply(df, "collect = {}")?;
+    assert_eq!(df.height(), 2);
+    Ok(())
+}
+
+#[test]
+fn incremental_state() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("state")?;
+    std::fs::create_dir_all(dir.join("input"))?;
+    std::fs::write(dir.join("input/1.csv"), "a\n1\n")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        source.data.csv.path = "input/*.csv"
+        state.path = "state.json"
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "export"
+        "#,
+    )?;
+    let run = || Config::from_path(dir.join("config.toml"), |config| config.run());
+    run()?;
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/export.csv"))?,
+        "a\n1\n"
+    );
+    std::fs::write(dir.join("input/2.csv"), "a\n2\n")?;
+    run()?;
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/export.csv"))?,
+        "a\n2\n"
+    );
+    // Nothing new, so the previous export is left untouched.
+    run()?;
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/export.csv"))?,
+        "a\n2\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn incremental_state_inspect() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("state_inspect")?;
+    std::fs::create_dir_all(dir.join("input"))?;
+    std::fs::write(dir.join("input/1.csv"), "a\n1\n")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        source.data.csv.path = "input/*.csv"
+        state.path = "state.json"
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "export"
+        "#,
+    )?;
+    let inspected = || {
+        Config::from_path(dir.join("config.toml"), |config| {
+            let mut inspected = None;
+            config.run_with(|lf| {
+                inspected = Some(lf.clone().collect()?);
+                Ok(())
+            })?;
+            Ok(inspected.expect("inspect was not called"))
+        })
+    };
+    assert_eq!(inspected()?, df!("a" => [1i64])?);
+    // The inspected data is what gets exported, limited to the files that are new to the state.
+    std::fs::write(dir.join("input/2.csv"), "a\n2\n")?;
+    assert_eq!(inspected()?, df!("a" => [2i64])?);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/export.csv"))?,
+        "a\n2\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn incremental_state_ignores_lookups() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("state_lookups")?;
+    std::fs::create_dir_all(dir.join("input"))?;
+    std::fs::create_dir_all(dir.join("lookup"))?;
+    std::fs::write(dir.join("input/1.csv"), "id\n1\n")?;
+    std::fs::write(dir.join("lookup/1.csv"), "id,label\n1,one\n")?;
+    std::fs::write(dir.join("lookup/2.csv"), "id,label\n2,two\n")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        source.data.csv.path = "input/*.csv"
+        state.path = "state.json"
+
+        [[transforms]]
+        join = { right.data.csv.path = "lookup/*.csv", how = "left", left_on = [{ expr.col = "id" }], right_on = [{ expr.col = "id" }] }
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "export"
+        "#,
+    )?;
+    let run = || Config::from_path(dir.join("config.toml"), |config| config.run());
+    run()?;
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/export.csv"))?,
+        "id,label\n1,one\n"
+    );
+    // The lookup files are unchanged, but are still loaded in full alongside the new input.
+    std::fs::write(dir.join("input/2.csv"), "id\n2\n")?;
+    run()?;
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/export.csv"))?,
+        "id,label\n2,two\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn include_file_path() -> Result<()> {
+    let dir = scratch_dir("include_file_path")?;
+    std::fs::write(dir.join("1.csv"), "a\n1\n")?;
+    std::fs::write(dir.join("2.csv"), "a\n2\n")?;
+    let df = load(&format!(
+        "csv = {{ path = '{}/*.csv', include_file_path = '' }}",
+        dir.display()
+    ))?
+    .sort(["a"], Default::default())?;
+    let paths = df
+        .column("file_path")?
+        .str()?
+        .into_no_null_iter()
+        .collect::<Vec<_>>();
+    assert!(paths[0].ends_with("1.csv"));
+    assert!(paths[1].ends_with("2.csv"));
+    Ok(())
+}
+
+#[test]
+fn diagonal_source_concat() -> Result<()> {
+    let dir = scratch_dir("diagonal_source_concat")?;
+    std::fs::write(dir.join("1.csv"), "a,b\n1,x\n")?;
+    std::fs::write(dir.join("2.csv"), "a,c\n2,true\n")?;
+    let df = load(&format!(
+        "csv = {{ path = '{}/*.csv', concat = 'diagonal' }}",
+        dir.display()
+    ))?
+    .sort(["a"], Default::default())?;
+    assert_eq!(df.get_column_names_str(), ["a", "b", "c"]);
+    assert_eq!(
+        df.column("b")?.str()?.into_iter().collect::<Vec<_>>(),
+        [Some("x"), None]
+    );
+    assert_eq!(
+        df.column("c")?.bool()?.into_iter().collect::<Vec<_>>(),
+        [None, Some(true)]
+    );
+    Ok(())
+}
+
+#[test]
+fn ndjson_inference() -> Result<()> {
+    let dir = scratch_dir("ndjson_inference")?;
+    let mut data = "{\"a\":1}\n".repeat(150);
+    data.push_str("{\"a\":2,\"b\":\"late\"}\n");
+    std::fs::write(dir.join("data.jsonl"), data)?;
+    let path = dir.join("data.jsonl");
+    let df = load(&format!(
+        "json_line = {{ path = '{}', infer_schema_length = 0 }}",
+        path.display()
+    ))?;
+    assert_eq!(df.height(), 151);
+    assert_eq!(df.column("b")?.null_count(), 150);
+    let df = load(&format!(
+        "json_line = {{ path = '{}', n_rows = 10 }}",
+        path.display()
+    ))?;
+    assert_eq!(df.height(), 10);
+    Ok(())
+}
+
+#[test]
+fn literal_source() -> Result<()> {
+    let df = load(
+        r#"
+[literal]
+format = "csv"
+data = """
+code,label
+A,Active
+I,Inactive
+"""
+"#,
+    )?;
+    assert_eq!(df.shape(), (2, 2));
+    assert_eq!(
+        df.column("label")?
+            .str()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        ["Active", "Inactive"]
+    );
+    Ok(())
+}
+
+#[test]
+fn example_config() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let config = Config::from_path(concat!(env!("CARGO_MANIFEST_DIR"), "/test.toml"), Ok)?;
+    assert_eq!(config.exports.len(), 1);
+    let df = config
+        .load()?
+        .collect()?
+        .sort(["Foo"], Default::default())?;
+    assert_eq!(
+        df.get_column_names_str(),
+        ["Foo", "Bar", "Baz", "Foobar", "Barbaz", "foobar"]
+    );
+    assert_eq!(
+        df.column("Foobar")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [5, 8, 2]
+    );
+    Ok(())
+}
+
+#[test]
+fn run_join_export() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("run_join_export")?;
+    std::fs::write(dir.join("left.csv"), "id,name\n1,a\n2,b\n3,c\n")?;
+    std::fs::write(dir.join("right.csv"), "id,score\n3,30\n1,10\n")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        source.data.csv.path = "left.csv"
+
+        [[transforms]]
+        [transforms.join]
+        right.data.csv.path = "right.csv"
+        how = "left"
+        left_on = [{ expr.col = "id" }]
+        right_on = [{ expr.col = "id" }]
+
+        [[transforms]]
+        sort_by = [{ column = "id" }]
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "joined"
+        "#,
+    )?;
+    Config::from_path(dir.join("config.toml"), |config| config.run())?;
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/joined.csv"))?,
+        "id,name,score\n1,a,10\n2,b,\n3,c,30\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn lit_list() -> Result<()> {
+    let df = df!("a" => [1i64, 2, 3, 4])?;
+    let out = apply(
+        df,
+        r#"
+        [[select]]
+        expr.col = "a"
+        ops = [{ is_in = { expr.lit_list = { values = [1, 3], dtype = "Int64" } } }]
+        "#,
+    )?;
+    assert_eq!(
+        out.column("a")?
+            .bool()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [true, false, true, false]
+    );
+    Ok(())
+}
+
+#[test]
+fn lit_list_float_from_integers() -> Result<()> {
+    let df = df!("a" => [1.0f64, 1.5, 2.0])?;
+    let out = apply(
+        df,
+        r#"select = [{ expr.col = "a", ops = [{ is_in = { expr.lit_list = { values = [1, 2], dtype = "Float64" } } }] }]"#,
+    )?;
+    assert_eq!(
+        out.column("a")?
+            .bool()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [true, false, true]
+    );
+    Ok(())
+}
+
+#[test]
+fn repeat() -> Result<()> {
+    let df = df!("a" => [1i64, 2, 3])?;
+    let out = apply(
+        df,
+        r#"with_columns = [{ expr.repeat = { value.expr.lit = "x" }, ops = [{ alias = "b" }] }]"#,
+    )?;
+    assert_eq!(
+        out.column("b")?
+            .str()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        ["x", "x", "x"]
+    );
+    Ok(())
+}
+
+#[test]
+fn cols() -> Result<()> {
+    let df = df!(
+        "a" => ["1", "2"],
+        "b" => ["3", "4"],
+        "c" => ["5", "6"],
+        "d" => ["7", "8"],
+    )?;
+    let out = apply(
+        df,
+        r#"with_columns = [{ expr.cols = ["a", "b", "c"], ops = [{ cast = "Int64" }] }]"#,
+    )?;
+    let dtypes = out.dtypes();
+    assert_eq!(
+        dtypes,
+        [
+            DataType::Int64,
+            DataType::Int64,
+            DataType::Int64,
+            DataType::String
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn positional_columns() -> Result<()> {
+    let df = df!(
+        "x" => [1i64],
+        "y" => [2i64],
+        "z" => [3i64],
+    )?;
+    let out = apply(df.clone(), r#"select = [{ expr = "last_col" }]"#)?;
+    assert_eq!(out.get_column_names_str(), ["z"]);
+    let out = apply(df.clone(), r#"select = [{ expr = "first_col" }]"#)?;
+    assert_eq!(out.get_column_names_str(), ["x"]);
+    let out = apply(df, r#"select = [{ expr.nth = -2 }]"#)?;
+    assert_eq!(out.get_column_names_str(), ["y"]);
+    Ok(())
+}
+
+#[test]
+fn date_range() -> Result<()> {
+    let out = apply(
+        DataFrame::empty(),
+        r#"
+        [[select]]
+        expr.date_range = { start = "2024-01-01", end = "2024-01-31", interval = "1d" }
+        ops = [{ alias = "day" }]
+        "#,
+    )?;
+    assert_eq!(out.height(), 31);
+    assert_eq!(out.column("day")?.dtype(), &DataType::Date);
+    let err = apply(
+        DataFrame::empty(),
+        r#"select = [{ expr.date_range = { start = "2024-01-01", end = "2024-01-31", interval = "1x" } }]"#,
+    )
+    .unwrap_err();
+    assert!(format!("{err:#}").contains("not a valid interval"));
+    Ok(())
+}
+
+#[test]
+fn int_range_end() -> Result<()> {
+    let out = apply(
+        DataFrame::empty(),
+        r#"
+        [[select]]
+        expr.int_range = { start = 0, end = 10, step = 2, dtype = "Int64" }
+        ops = [{ alias = "n" }]
+        "#,
+    )?;
+    assert_eq!(
+        out.column("n")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [0, 2, 4, 6, 8]
+    );
+    Ok(())
+}
+
+#[test]
+fn separator() -> Result<()> {
+    use sources::Separator;
+
+    assert_eq!(Separator::try_from(",".to_owned())?.0, b',');
+    assert_eq!(Separator::try_from("\t".to_owned())?.0, b'\t');
+    assert_eq!(Separator::try_from(r"\t".to_owned())?.0, b'\t');
+    assert!(Separator::try_from("é".to_owned()).is_err());
+    assert!(Separator::try_from(";;".to_owned()).is_err());
+    Ok(())
+}
+
+#[test]
+fn headerless_csv_schema() -> Result<()> {
+    use sources::CsvSource;
+
+    let dir = scratch_dir("headerless_csv_schema")?;
+    std::fs::write(dir.join("data.csv"), "1,a\n2,b\n")?;
+    let source = CsvSource {
+        path: dir.join("data.csv").try_into()?,
+        separator: None,
+        has_header: Some(false),
+        schema: Some(sources::Schema(Schema::from_iter([
+            Field::new("id".into(), DataType::Int64),
+            Field::new("name".into(), DataType::String),
+        ]))),
+        include_file_path: None,
+        concat: Default::default(),
+        extension: None,
+        allow_empty: false,
+        rechunk: None,
+    };
+    let df = source.load()?.collect()?;
+    assert_eq!(df.get_column_names_str(), ["id", "name"]);
+    assert_eq!(
+        df.column("id")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 2]
+    );
+    Ok(())
+}
+
+#[test]
+fn parallel_exports() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("parallel_exports")?;
+    std::fs::write(dir.join("data.csv"), "a\n1\n2\n")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        source.data.csv.path = "data.csv"
+        run = { parallel = true, materialize_once = true }
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "export"
+
+        [[exports]]
+        type = "nd_json"
+        folder = "output"
+        name = "export"
+        "#,
+    )?;
+    Config::from_path(dir.join("config.toml"), |config| config.run())?;
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/export.csv"))?,
+        "a\n1\n2\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/export.jsonl"))?,
+        "{\"a\":1}\n{\"a\":2}\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn materialize_once() -> Result<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("materialize_once")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        source.data.literal = { format = "csv", data = "a\n1\n" }
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "first"
+
+        [[exports]]
+        type = "parquet"
+        folder = "output"
+        name = "second"
+        "#,
+    )?;
+    let reads = |materialize_once: bool| -> Result<usize> {
+        static READS: AtomicUsize = AtomicUsize::new(0);
+        READS.store(0, Ordering::SeqCst);
+        Config::from_path(dir.join("config.toml"), |mut config| {
+            config.run.materialize_once = materialize_once;
+            let lf = config.load()?.with_columns([col("a").map(
+                |column| {
+                    READS.fetch_add(1, Ordering::SeqCst);
+                    Ok(Some(column))
+                },
+                GetOutput::same_type(),
+            )]);
+            config.export(lf)
+        })?;
+        Ok(READS.load(Ordering::SeqCst))
+    };
+    assert_eq!(reads(false)?, 2);
+    assert_eq!(reads(true)?, 1);
+    Ok(())
+}
+
+#[test]
+fn error_context() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("error_context")?;
+    let path = dir.join("config.toml");
+    std::fs::write(
+        &path,
+        r#"
+source.data.literal = { format = "csv", data = "a\n1\n" }
+
+[[transforms]]
+limit = 1
+
+[[transforms]]
+[transforms.join]
+right.data.literal = { format = "csv", data = "a\n1\n" }
+right.transforms = [{ cut = { column = "a", breaks = [1.0], labels = ["x"] } }]
+how = "left"
+left_on = [{ expr.col = "a" }]
+right_on = [{ expr.col = "a" }]
+"#,
+    )?;
+    let err = Config::from_path(&path, |config| config.load().map(|_| ())).unwrap_err();
+    let message = format!("{err:#}");
+    assert!(message.contains("config.toml"), "{message}");
+    assert!(
+        message.contains("transforms[1]: join.right: transforms[0]"),
+        "{message}"
+    );
+    Ok(())
+}
+
+#[test]
+fn definitions() -> Result<()> {
+    let config: Config = toml::from_str(
+        r#"
+source.data.literal = { format = "csv", data = "a,b\n1,2\n2,1\n" }
+
+[definitions.loaders.labels]
+data.literal = { format = "csv", data = "id,label\n1,one\n2,two\n" }
+
+[definitions.transforms]
+tidy = [{ select = [{ expr.col = "a_label" }, { expr.col = "label" }] }]
+
+[[transforms]]
+[transforms.join]
+right.data.ref = "labels"
+how = "left"
+left_on = [{ expr.col = "a" }]
+right_on = [{ expr.col = "id" }]
+
+[[transforms]]
+rename.map = { label = "a_label" }
+
+[[transforms]]
+[transforms.join]
+right.data.ref = "labels"
+how = "left"
+left_on = [{ expr.col = "b" }]
+right_on = [{ expr.col = "id" }]
+
+[[transforms]]
+ref = "tidy"
+"#,
+    )?;
+    let df = config.load()?.collect()?;
+    assert_eq!(df.get_column_names_str(), ["a_label", "label"]);
+    assert_eq!(
+        df.column("a_label")?.str()?.into_iter().collect::<Vec<_>>(),
+        [Some("one"), Some("two")]
+    );
+    assert_eq!(
+        df.column("label")?.str()?.into_iter().collect::<Vec<_>>(),
+        [Some("two"), Some("one")]
+    );
+
+    let unknown: Config = toml::from_str(
+        r#"
+source.data.ref = "missing"
+"#,
+    )?;
+    let err = unknown.load().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(utils::Error::UnknownDefinition(_))
+    ));
+
+    let circular: Config = toml::from_str(
+        r#"
+source.data.ref = "a"
+
+[definitions.loaders.a]
+data.ref = "b"
+
+[definitions.loaders.b]
+data.ref = "a"
+"#,
+    )?;
+    let err = circular.load().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(utils::Error::CircularDefinition(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn n_unique() -> Result<()> {
+    let df = df!(
+        "day" => [1i64, 1, 1, 2, 2],
+        "user" => ["a", "b", "a", "c", "c"],
+    )?;
+    let out = apply(
+        df,
+        r#"
+        [group_by]
+        exprs = [{ expr.col = "day" }]
+        agg = [
+            { expr.col = "user", ops = ["n_unique"] },
+            { expr.col = "user", ops = ["count_distinct", { alias = "distinct" }] },
+        ]
+        "#,
+    )?
+    .sort(["day"], Default::default())?;
+    let counts = |name| -> Result<Vec<IdxSize>> {
+        Ok(out.column(name)?.idx()?.into_no_null_iter().collect())
+    };
+    assert_eq!(counts("user")?, [2, 1]);
+    assert_eq!(counts("distinct")?, [2, 1]);
+    Ok(())
+}
+
+#[test]
+fn aggregations() -> Result<()> {
+    let df = df!(
+        "g" => ["a", "a", "a", "b", "b"],
+        "x" => [1.0, 2.0, 3.0, 4.0, 8.0],
+    )?;
+    let out = apply(
+        df,
+        r#"
+        [group_by]
+        exprs = [{ expr.col = "g" }]
+        agg = [
+            { expr.col = "x", ops = ["mean", { alias = "mean" }] },
+            { expr.col = "x", ops = [{ std = {} }, { alias = "std" }] },
+            { expr.col = "x", ops = [{ var = { ddof = 0 } }, { alias = "var" }] },
+            { expr.col = "x", ops = ["median", { alias = "median" }] },
+            { expr.col = "x", ops = ["sum", { alias = "sum" }] },
+            { expr.col = "x", ops = ["min", { alias = "min" }] },
+            { expr.col = "x", ops = ["max", { alias = "max" }] },
+            { expr.col = "x", ops = ["first", { alias = "first" }] },
+            { expr.col = "x", ops = ["last", { alias = "last" }] },
+        ]
+        "#,
+    )?
+    .sort(["g"], Default::default())?;
+    let values =
+        |name| -> Result<Vec<f64>> { Ok(out.column(name)?.f64()?.into_no_null_iter().collect()) };
+    assert_eq!(values("mean")?, [2.0, 6.0]);
+    let close = |a: Vec<f64>, b: [f64; 2]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9);
+    assert!(close(values("std")?, [1.0, 8f64.sqrt()]));
+    assert!(close(values("var")?, [2.0 / 3.0, 4.0]));
+    assert_eq!(values("median")?, [2.0, 6.0]);
+    assert_eq!(values("sum")?, [6.0, 12.0]);
+    assert_eq!(values("min")?, [1.0, 4.0]);
+    assert_eq!(values("max")?, [3.0, 8.0]);
+    assert_eq!(values("first")?, [1.0, 4.0]);
+    assert_eq!(values("last")?, [3.0, 8.0]);
+    Ok(())
+}
+
+#[test]
+fn quantile() -> Result<()> {
+    let df = df!(
+        "g" => ["a", "a", "a", "a", "a", "b", "b", "b"],
+        "latency" => [1.0, 2.0, 3.0, 4.0, 5.0, 10.0, 20.0, 30.0],
+    )?;
+    let out = apply(
+        df.clone(),
+        r#"
+        [group_by]
+        exprs = [{ expr.col = "g" }]
+        agg = [
+            { expr.col = "latency", ops = [{ quantile = { quantile = 0.5 } }, { alias = "p50" }] },
+            { expr.col = "latency", ops = [{ quantile = { quantile = 0.9, interpolation = "linear" } }, { alias = "p90" }] },
+        ]
+        "#,
+    )?
+    .sort(["g"], Default::default())?;
+    let values =
+        |name| -> Result<Vec<f64>> { Ok(out.column(name)?.f64()?.into_no_null_iter().collect()) };
+    assert_eq!(values("p50")?, [3.0, 20.0]);
+    let p90 = values("p90")?;
+    assert!((p90[0] - 4.6).abs() < 1e-9 && (p90[1] - 28.0).abs() < 1e-9);
+    assert!(apply(
+        df,
+        r#"select = [{ expr.col = "latency", ops = [{ quantile = { quantile = 1.5 } }] }]"#,
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn head_tail() -> Result<()> {
+    let df = df!(
+        "g" => ["a", "a", "a", "b", "b", "b"],
+        "score" => [1i64, 3, 2, 6, 4, 5],
+    )?;
+    let sorted = apply(df, r#"sort_by = [{ column = "score", descending = true }]"#)?;
+    let out = apply(
+        sorted,
+        r#"
+        [group_by]
+        exprs = [{ expr.col = "g" }]
+        agg = [
+            { expr.col = "score", ops = [{ head = 2 }, { alias = "top" }] },
+            { expr.col = "score", ops = [{ tail = 1 }, { alias = "bottom" }] },
+        ]
+        "#,
+    )?
+    .sort(["g"], Default::default())?;
+    let lists = |name| -> Result<Vec<Vec<i64>>> {
+        Ok(out
+            .column(name)?
+            .list()?
+            .into_no_null_iter()
+            .map(|s| s.i64().map(|ca| ca.into_no_null_iter().collect()))
+            .collect::<PolarsResult<_>>()?)
+    };
+    assert_eq!(lists("top")?, [vec![3, 2], vec![6, 5]]);
+    assert_eq!(lists("bottom")?, [vec![1], vec![4]]);
+    Ok(())
+}
+
+#[test]
+fn hash() -> Result<()> {
+    let df = df!("id" => ["alice", "bob", "alice"])?;
+    let hashes = |seed: u64| -> Result<Vec<u64>> {
+        let out = apply(
+            df.clone(),
+            &format!(
+                r#"select = [{{ expr.col = "id", ops = [{{ hash = {{ seed = {seed} }} }}] }}]"#
+            ),
+        )?;
+        Ok(out.column("id")?.u64()?.into_no_null_iter().collect())
+    };
+    let first = hashes(42)?;
+    assert_eq!(first, hashes(42)?);
+    assert_eq!(first[0], first[2]);
+    assert_ne!(first[0], first[1]);
+    assert_ne!(first, hashes(7)?);
+    Ok(())
+}
+
+#[test]
+fn cast_columns() -> Result<()> {
+    let df = df!(
+        "a" => ["1", "2"],
+        "b" => ["1.5", "x"],
+        "c" => [1i64, 0],
+    )?;
+    let out = apply(
+        df.clone(),
+        r#"cast = { columns = { a = "Int64", b = "Float64", c = "Boolean" } }"#,
+    )?;
+    assert_eq!(
+        out.dtypes(),
+        [DataType::Int64, DataType::Float64, DataType::Boolean]
+    );
+    assert_eq!(out.column("b")?.null_count(), 1);
+    assert!(apply(
+        df,
+        r#"cast = { columns = { b = "Float64" }, strict = true }"#,
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn drop_by_type() -> Result<()> {
+    let df = df!(
+        "a" => ["x", "y"],
+        "b" => [1i64, 2],
+        "c" => ["z", "w"],
+        "d" => [true, false],
+    )?;
+    let out = apply(df, r#"drop_by_type = ["String"]"#)?;
+    assert_eq!(out.get_column_names_str(), ["b", "d"]);
+    Ok(())
+}
+
+#[test]
+fn fill_null_frame() -> Result<()> {
+    let df = df!(
+        "a" => [Some(1i64), None, Some(3)],
+        "b" => [None, Some(2.5), None],
+    )?;
+    let out = apply(df.clone(), r#"fill_null = { strategy = "zero" }"#)?;
+    assert_eq!(
+        out.column("a")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 0, 3]
+    );
+    assert_eq!(
+        out.column("b")?
+            .f64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [0.0, 2.5, 0.0]
+    );
+    let out = apply(df.clone(), r#"fill_null = { strategy = "forward" }"#)?;
+    assert_eq!(
+        out.column("a")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 1, 3]
+    );
+    let out = apply(
+        df.clone(),
+        r#"fill_null = { subset.names = ["a"], strategy = "zero" }"#,
+    )?;
+    assert_eq!(out.column("a")?.null_count(), 0);
+    assert_eq!(out.column("b")?.null_count(), 2);
+    assert!(apply(df, r#"fill_null = {}"#).is_err());
+    Ok(())
+}
+
+#[test]
+fn rename_regex() -> Result<()> {
+    let df = df!(
+        "first name" => ["a"],
+        "last name" => ["b"],
+        "raw_id" => [1i64],
+    )?;
+    let out = apply(
+        df.clone(),
+        r#"rename.regex = { pattern = " ", replacement = "_" }"#,
+    )?;
+    assert_eq!(
+        out.get_column_names_str(),
+        ["first_name", "last_name", "raw_id"]
+    );
+    let out = apply(
+        df,
+        r#"rename.regex = { pattern = "^raw_(.*)$", replacement = "$1" }"#,
+    )?;
+    assert_eq!(
+        out.get_column_names_str(),
+        ["first name", "last name", "id"]
+    );
+    // Successive renames in one plan each see the names left by the last.
+    let mut lf = out.lazy();
+    for transform in [
+        r#"rename.regex = { pattern = " ", replacement = "_" }"#,
+        r#"rename.regex = { pattern = "_name$", replacement = "" }"#,
+    ] {
+        lf = toml::from_str::<TransformItem>(transform)?.transform(lf)?;
+    }
+    assert_eq!(
+        lf.collect()?.get_column_names_str(),
+        ["first", "last", "id"]
+    );
+    Ok(())
+}
+
+#[test]
+fn drop_duplicates_maintain_order() -> Result<()> {
+    let df = df!(
+        "id" => [3i64, 1, 2, 1, 3, 2],
+        "seen" => [30i64, 10, 20, 10, 30, 20],
+    )?;
+    let sorted = apply(df, r#"sort_by = [{ column = "seen", descending = true }]"#)?;
+    let out = apply(
+        sorted,
+        r#"drop_duplicates = { keep = "first", maintain_order = true }"#,
+    )?;
+    assert_eq!(
+        out.column("id")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [3, 2, 1]
+    );
+    let out = apply(
+        df!("id" => [1i64, 1, 2], "seen" => [10i64, 11, 20])?,
+        r#"drop_duplicates = { subset.names = ["id"], keep = "first", maintain_order = true }"#,
+    )?;
+    assert_eq!(
+        out.column("seen")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [10, 20]
+    );
+    Ok(())
+}
+
+#[test]
+fn concat_to_supertypes() -> Result<()> {
+    let df = df!("a" => [1i64, 2])?;
+    let transform = |to_supertypes: bool| {
+        format!(
+            r#"
+            [concat]
+            how = "vertical"
+            to_supertypes = {to_supertypes}
+            [[concat.sources]]
+            data.literal = {{ format = "csv", data = "a\n3\n" }}
+            transforms = [{{ cast = {{ columns = {{ a = "Int32" }} }} }}]
+            "#
+        )
+    };
+    let out = apply(df.clone(), &transform(true))?;
+    assert_eq!(out.column("a")?.dtype(), &DataType::Int64);
+    assert_eq!(
+        out.column("a")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 2, 3]
+    );
+    assert!(apply(df.clone(), &transform(false)).is_err());
+
+    // The deprecated `args` table still applies when the new fields are unset.
+    let out = apply(
+        df,
+        r#"
+        [concat]
+        how = "vertical"
+        args = { parallel = true, rechunk = false, to_supertypes = true, diagonal = false, from_partitioned_ds = false, maintain_order = true }
+        [[concat.sources]]
+        data.literal = { format = "csv", data = "a\n3\n" }
+        transforms = [{ cast = { columns = { a = "Int32" } } }]
+        "#,
+    )?;
+    assert_eq!(out.column("a")?.dtype(), &DataType::Int64);
+    Ok(())
+}
+
+#[test]
+fn secrets() -> Result<()> {
+    use utils::Secret;
+
+    let literal: Secret = toml::from_str(r#"literal = "hunter2""#)?;
+    assert_eq!(literal.resolve()?, "hunter2");
+    assert!(!format!("{literal:?}").contains("hunter2"));
+    assert!(!toml::to_string(&literal)?.contains("hunter2"));
+
+    std::env::set_var("RETL_TEST_SECRET", "from-env");
+    let env: Secret = toml::from_str(r#"env = "RETL_TEST_SECRET""#)?;
+    assert_eq!(env.resolve()?, "from-env");
+    let missing: Secret = toml::from_str(r#"env = "RETL_TEST_SECRET_MISSING""#)?;
+    assert!(missing.resolve().is_err());
+
+    let dir = scratch_dir("secrets")?;
+    std::fs::write(dir.join("secret.txt"), "from-file\n")?;
+    let file: Secret = toml::from_str(&format!("file = '{}'", dir.join("secret.txt").display()))?;
+    assert_eq!(file.resolve()?, "from-file");
+
+    let command: Secret = toml::from_str(r#"command = ["echo", "from-command"]"#)?;
+    assert_eq!(command.resolve()?, "from-command");
+    Ok(())
+}
+
+#[test]
+fn sql_export() -> Result<()> {
+    use exports::ExportItem;
+
+    let dir = scratch_dir("sql_export")?;
+    let db = dir.join("db.sqlite");
+    let export = |if_exists: &str| -> Result<exports::ExportReport> {
+        let export: ExportItem = toml::from_str(&format!(
+            "type = 'sql'\nconnection.literal = '{}'\ntable = 'people'\nif_exists = '{if_exists}'\nbatch_size = 2",
+            db.display()
+        ))?;
+        export.export(
+            df!(
+                "name" => [Some("a"), Some("b"), None],
+                "age" => [1i64, 2, 3],
+                "score" => [0.5, 1.5, 2.5],
+            )?
+            .lazy(),
+        )
+    };
+    assert_eq!(export("fail")?.rows, Some(3));
+    let count = || -> Result<i64> {
+        Ok(rusqlite::Connection::open(&db)?.query_row(
+            "SELECT count(*) FROM people",
+            [],
+            |row| row.get(0),
+        )?)
+    };
+    assert_eq!(count()?, 3);
+    assert!(export("fail").is_err());
+    export("append")?;
+    assert_eq!(count()?, 6);
+    export("replace")?;
+    assert_eq!(count()?, 3);
+    let (name, age, score): (Option<String>, i64, f64) = rusqlite::Connection::open(&db)?
+        .query_row(
+            "SELECT name, age, score FROM people ORDER BY age DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+    assert_eq!((name, age, score), (None, 3, 2.5));
+    Ok(())
+}
+
+#[test]
+fn sql_export_failure_rolls_back() -> Result<()> {
+    use exports::ExportItem;
+
+    let dir = scratch_dir("sql_export_failure_rolls_back")?;
+    let db = dir.join("db.sqlite");
+    let conn = rusqlite::Connection::open(&db)?;
+    conn.execute("CREATE TABLE people (age INTEGER UNIQUE)", [])?;
+    conn.execute("INSERT INTO people VALUES (3)", [])?;
+    let export = |if_exists: &str, df: DataFrame| -> Result<exports::ExportReport> {
+        let export: ExportItem = toml::from_str(&format!(
+            "type = 'sql'\nconnection.literal = '{}'\ntable = 'people'\nif_exists = '{if_exists}'\nbatch_size = 1",
+            db.display()
+        ))?;
+        export.export(df.lazy())
+    };
+    let count = || -> Result<i64> {
+        Ok(conn.query_row("SELECT count(*) FROM people", [], |row| row.get(0))?)
+    };
+    // The last row conflicts with the existing one, after the first two have been inserted.
+    assert!(export("append", df!("age" => [1i64, 2, 3])?).is_err());
+    assert_eq!(count()?, 1);
+    // SQLite column names are case-insensitive, so creating the new table fails after the old one is dropped.
+    assert!(export("replace", df!("age" => [1i64], "AGE" => [2i64])?).is_err());
+    assert_eq!(count()?, 1);
+    Ok(())
+}
+
+#[test]
+fn excel_export() -> Result<()> {
+    use calamine::{open_workbook, Data, Reader, Xlsx};
+    use exports::ExportItem;
+
+    let dir = scratch_dir("excel_export")?;
+    let export: ExportItem = toml::from_str(&format!(
+        "type = 'excel'\nfolder = '{}'\nname = 'report'\nsheet_name = 'data'",
+        dir.display()
+    ))?;
+    export.export(
+        df!(
+            "name" => [Some("a"), None],
+            "count" => [1i64, 2],
+            "flag" => [true, false],
+        )?
+        .lazy(),
+    )?;
+    let mut workbook: Xlsx<_> = open_workbook(dir.join("report.xlsx"))?;
+    let range = workbook.worksheet_range("data")?;
+    assert_eq!(range.get_size(), (3, 3));
+    assert_eq!(range.get_value((0, 0)), Some(&Data::String("name".into())));
+    assert_eq!(range.get_value((1, 0)), Some(&Data::String("a".into())));
+    assert_eq!(range.get_value((2, 0)), Some(&Data::Empty));
+    assert_eq!(range.get_value((2, 1)), Some(&Data::Float(2.0)));
+    assert_eq!(range.get_value((1, 2)), Some(&Data::Bool(true)));
+    Ok(())
+}
+
+#[test]
+fn export_manifest() -> Result<()> {
+    use exports::Manifest;
+
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("export_manifest")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        source.data.literal = { format = "csv", data = "a,b\n1,x\n2,y\n3,z\n" }
+        run = { write_manifest = true }
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "export"
+
+        [[exports]]
+        type = "parquet"
+        folder = "output"
+        name = "export"
+        "#,
+    )?;
+    Config::from_path(dir.join("config.toml"), |config| config.run())?;
+    let manifest: Manifest = serde_json::from_reader(std::fs::File::open(
+        dir.join("output/export.csv.manifest.json"),
+    )?)?;
+    let exported = dir.join("output/export.csv").canonicalize()?;
+    assert_eq!(manifest.path, exported);
+    assert_eq!(manifest.rows, 3);
+    assert_eq!(
+        manifest.schema,
+        [
+            ("a".to_owned(), "i64".to_owned()),
+            ("b".to_owned(), "str".to_owned())
+        ]
+    );
+    assert_eq!(manifest.bytes, std::fs::metadata(&exported)?.len());
+    // An export with the same name in another format gets its own manifest.
+    let manifest: Manifest = serde_json::from_reader(std::fs::File::open(
+        dir.join("output/export.parquet.manifest.json"),
+    )?)?;
+    assert_eq!(
+        manifest.path,
+        dir.join("output/export.parquet").canonicalize()?
+    );
+    Ok(())
+}
+
+#[test]
+fn json_export_orient() -> Result<()> {
+    use exports::ExportItem;
+
+    let dir = scratch_dir("json_export_orient")?;
+    let export = |options: &str| -> Result<String> {
+        let export: ExportItem = toml::from_str(&format!(
+            "type = 'json'\nfolder = '{}'\nname = 'export'\n{options}",
+            dir.display()
+        ))?;
+        export.export(df!("b" => [1i64, 2], "a" => ["x", "y"])?.lazy())?;
+        Ok(std::fs::read_to_string(dir.join("export.json"))?)
+    };
+    assert_eq!(export("")?, r#"[{"b":1,"a":"x"},{"b":2,"a":"y"}]"#);
+    assert_eq!(
+        export("orient = 'columns'")?,
+        r#"{"b":[1,2],"a":["x","y"]}"#
+    );
+    let pretty = export("orient = 'columns'\npretty = true")?;
+    assert!(pretty.contains('\n'));
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&pretty)?,
+        serde_json::json!({ "b": [1, 2], "a": ["x", "y"] })
+    );
+    // Characters inside strings are left alone when indenting.
+    let export = |df: DataFrame| -> Result<String> {
+        let export: ExportItem = toml::from_str(&format!(
+            "type = 'json'\nfolder = '{}'\nname = 'export'\norient = 'columns'\npretty = true",
+            dir.display()
+        ))?;
+        export.export(df.lazy())?;
+        Ok(std::fs::read_to_string(dir.join("export.json"))?)
+    };
+    let df = df!("a" => [r#"{"x": [1, 2]}"#, ""])?;
+    assert_eq!(
+        export(df.clone())?,
+        "{\n  \"a\": [\n    \"{\\\"x\\\": [1, 2]}\",\n    \"\"\n  ]\n}"
+    );
+    assert_eq!(export(df.head(Some(0)))?, "{\n  \"a\": []\n}");
+    Ok(())
+}
+
+#[test]
+fn export_compression() -> Result<()> {
+    use exports::ExportItem;
+    use std::io::Read;
+
+    let dir = scratch_dir("export_compression")?;
+    let export = |format: &str, compression: &str| -> Result<()> {
+        let export: ExportItem = toml::from_str(&format!(
+            "type = '{format}'\nfolder = '{}'\nname = 'export'\ncompression = '{compression}'",
+            dir.display()
+        ))?;
+        export.export(df!("a" => [1i64, 2])?.lazy())?;
+        Ok(())
+    };
+    export("csv", "gzip")?;
+    let mut csv = String::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(dir.join("export.csv.gz"))?)
+        .read_to_string(&mut csv)?;
+    assert_eq!(csv, "a\n1\n2\n");
+
+    export("nd_json", "zstd")?;
+    let jsonl = zstd::decode_all(std::fs::File::open(dir.join("export.jsonl.zst"))?)?;
+    assert_eq!(String::from_utf8(jsonl)?, "{\"a\":1}\n{\"a\":2}\n");
+    Ok(())
+}
+
+#[test]
+fn condition_column_comparison() -> Result<()> {
+    let df = df!("a" => [1i64, 5, 3], "b" => [4i64, 2, 3])?;
+    let out = apply(
+        df,
+        r#"
+        [[select]]
+        expr.condition.when = { expr.col = "a", ops = [{ gt = { expr.col = "b" } }] }
+        expr.condition.then = { expr.col = "a" }
+        expr.condition.otherwise = { expr.col = "b" }
+        ops = [{ alias = "larger" }]
+
+        [[select]]
+        expr.condition.when = { expr.col = "a", ops = [{ lt = { expr.col = "b" } }] }
+        expr.condition.then = { expr.col = "a" }
+        expr.condition.otherwise = { expr.col = "b" }
+        ops = [{ alias = "smaller" }]
+        "#,
+    )?;
+    let values =
+        |name| -> Result<Vec<i64>> { Ok(out.column(name)?.i64()?.into_no_null_iter().collect()) };
+    assert_eq!(values("larger")?, [4, 5, 3]);
+    assert_eq!(values("smaller")?, [1, 2, 3]);
+    Ok(())
+}
+
+#[test]
+fn arg_min_max() -> Result<()> {
+    let df = df!(
+        "g" => ["a", "a", "a", "b", "b"],
+        "score" => [2i64, 9, 4, 7, 1],
+    )?;
+    let out = apply(
+        df,
+        r#"
+        [group_by]
+        exprs = [{ expr.col = "g" }]
+        agg = [
+            { expr.col = "score", ops = ["arg_max", { alias = "max_index" }] },
+            { expr.col = "score", ops = ["arg_min", { alias = "min_index" }] },
+        ]
+        "#,
+    )?
+    .sort(["g"], Default::default())?;
+    let indices = |name| -> Result<Vec<IdxSize>> {
+        Ok(out.column(name)?.idx()?.into_no_null_iter().collect())
+    };
+    assert_eq!(indices("max_index")?, [1, 0]);
+    assert_eq!(indices("min_index")?, [0, 1]);
+    Ok(())
+}
+
+#[test]
+fn gather() -> Result<()> {
+    let df = df!(
+        "a" => [10i64, 20, 30, 40],
+        "index" => [0i64, -1, 1, -2],
+    )?;
+    let out = apply(
+        df,
+        r#"select = [{ expr.col = "a", ops = [{ gather = { expr.col = "index" } }] }]"#,
+    )?;
+    assert_eq!(
+        out.column("a")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [10, 40, 20, 30]
+    );
+    Ok(())
+}
+
+#[test]
+fn distinct_flags() -> Result<()> {
+    let df = df!("a" => [1i64, 2, 1, 3, 2], "n" => [0i64, 1, 2, 3, 4])?;
+    let kept = |op: &str| -> Result<Vec<i64>> {
+        let out = apply(
+            df.clone(),
+            &format!(r#"filter = [{{ expr.col = "a", ops = ["{op}"] }}]"#),
+        )?;
+        Ok(out.column("n")?.i64()?.into_no_null_iter().collect())
+    };
+    assert_eq!(kept("is_first_distinct")?, [0, 1, 3]);
+    assert_eq!(kept("is_last_distinct")?, [2, 3, 4]);
+    assert_eq!(kept("is_duplicated")?, [0, 1, 2, 4]);
+    Ok(())
+}
+
+#[test]
+fn null_count() -> Result<()> {
+    let df = df!(
+        "g" => ["a", "a", "a", "b", "b"],
+        "x" => [Some(1i64), None, None, Some(4), Some(5)],
+    )?;
+    let out = apply(
+        df,
+        r#"group_by = { exprs = [{ expr.col = "g" }], agg = [{ expr.col = "x", ops = ["null_count"] }] }"#,
+    )?
+    .sort(["g"], Default::default())?;
+    assert_eq!(
+        out.column("x")?
+            .idx()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [2, 0]
+    );
+    Ok(())
+}
+
+#[test]
+fn profile_stages() -> Result<()> {
+    use std::{io::Write, sync::Arc};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let config: Config = toml::from_str(
+        r#"
+        source.data.literal = { format = "csv", data = "a\n1\n2\n3\n" }
+        run = { profile = true }
+        transforms = [{ limit = 2 }]
+        "#,
+    )?;
+    let captured = Captured::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer({
+            let captured = captured.clone();
+            move || captured.clone()
+        })
+        .finish();
+    let df = tracing::subscriber::with_default(subscriber, || config.load())?.collect()?;
+    assert_eq!(df.height(), 2);
+    let logs = String::from_utf8(captured.0.lock().unwrap().clone())?;
+    assert!(logs.contains("source: "), "{logs}");
+    assert!(logs.contains("3 rows"), "{logs}");
+    assert!(logs.contains("transforms[0]: "), "{logs}");
+    assert!(logs.contains("3 -> 2 rows"), "{logs}");
+    assert!(logs.contains("total: "), "{logs}");
+    Ok(())
+}
+
+#[test]
+fn seeded_run() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("seeded_run")?;
+    let data = (0..100).map(|i| format!("{i}\n")).collect::<String>();
+    std::fs::write(
+        dir.join("config.toml"),
+        format!(
+            r#"
+            source.data.literal = {{ format = "csv", data = "a\n{}" }}
+            transforms = [{{ select = [{{ expr.col = "a", ops = ["shuffle"] }}] }}]
+            run = {{ seed = 42 }}
+
+            [[exports]]
+            type = "csv"
+            folder = "output"
+            name = "export"
+            "#,
+            data.replace('\n', "\\n")
+        ),
+    )?;
+    let output = || -> Result<String> {
+        Config::from_path(dir.join("config.toml"), |config| config.run())?;
+        Ok(std::fs::read_to_string(dir.join("output/export.csv"))?)
+    };
+    let first = output()?;
+    assert_ne!(first, format!("a\n{data}"));
+    assert_eq!(first, output()?);
+    Ok(())
+}
+
+#[test]
+fn shuffle_matches_across_exports() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("shuffle_matches_across_exports")?;
+    let data = (0..100).map(|i| format!("{i}\\n")).collect::<String>();
+    std::fs::write(
+        dir.join("config.toml"),
+        format!(
+            r#"
+            source.data.literal = {{ format = "csv", data = "a\\n{data}" }}
+            transforms = [{{ select = [{{ expr.col = "a", ops = ["shuffle"] }}] }}]
+            run = {{ parallel = true }}
+
+            [[exports]]
+            type = "csv"
+            folder = "output"
+            name = "first"
+
+            [[exports]]
+            type = "csv"
+            folder = "output"
+            name = "second"
+            "#
+        ),
+    )?;
+    Config::from_path(dir.join("config.toml"), |config| config.run())?;
+    assert_eq!(
+        std::fs::read_to_string(dir.join("output/first.csv"))?,
+        std::fs::read_to_string(dir.join("output/second.csv"))?
+    );
+    Ok(())
+}
+
+#[test]
+fn config_schema() -> Result<()> {
+    let config: Config = toml::from_str(
+        r#"
+        source.data.literal = { format = "csv", data = "a,b\n1,x\n2,y\n" }
+        transforms = [{ select = [{ expr.col = "b" }, { expr.col = "a" }] }]
+        "#,
+    )?;
+    let schema = config.schema()?;
+    assert_eq!(schema.iter_names().collect::<Vec<_>>(), ["b", "a"]);
+    assert_eq!(
+        schema.iter_values().cloned().collect::<Vec<_>>(),
+        [DataType::String, DataType::Int64]
+    );
+    Ok(())
+}
+
+#[test]
+fn struct_field_by_index() -> Result<()> {
+    let df = df!("a" => [1i64, 2], "b" => ["x", "y"])?
+        .into_struct("s".into())
+        .into_series()
+        .into_frame();
+    let out = apply(
+        df.clone(),
+        r#"select = [{ expr.col = "s", ops = [{ struct = { field_by_index = 1 } }] }]"#,
+    )?;
+    assert_eq!(
+        out.column("b")?
+            .str()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        ["x", "y"]
+    );
+    assert!(apply(
+        df,
+        r#"select = [{ expr.col = "s", ops = [{ struct = { field_by_index = 2 } }] }]"#,
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn unnest_columns_only() -> Result<()> {
+    let df = df!("id" => [1i64, 2], "name" => ["x", "y"])?
+        .into_struct("s".into())
+        .into_series()
+        .into_frame();
+    let out = apply(df.clone(), r#"unnest.names = ["s"]"#)?;
+    assert_eq!(out.get_column_names_str(), ["id", "name"]);
+    // The serialized polars selector accepted before options were added.
+    let out = apply(df, r#"unnest.ByName = { names = ["s"], strict = true }"#)?;
+    assert_eq!(out.get_column_names_str(), ["id", "name"]);
+    Ok(())
+}
+
+#[test]
+fn unnest_prefix() -> Result<()> {
+    let mut df = df!("id" => [1i64, 2], "name" => ["x", "y"])?
+        .into_struct("s".into())
+        .into_series()
+        .into_frame();
+    df.with_column(Series::new("id".into(), [10i64, 20]))?;
+    let out = apply(df, r#"unnest = { columns.names = ["s"], prefix = "s_" }"#)?;
+    assert_eq!(out.get_column_names_str(), ["s_id", "s_name", "id"]);
+    assert_eq!(
+        out.column("s_id")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 2]
+    );
+    assert_eq!(
+        out.column("id")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [10, 20]
+    );
+    Ok(())
+}
+
+#[test]
+fn extract_alias_collision() -> Result<()> {
+    let df = df!(
+        "code" => ["ab-12", "cd-34"],
+        "_code_groups" => [1i64, 2],
+    )?;
+    let out = apply(
+        df,
+        r#"extract = { column = "code", pattern = '(?<letters>[a-z]+)-(?<digits>\d+)', prefix = "code_" }"#,
+    )?;
+    assert_eq!(
+        out.get_column_names_str(),
+        ["code", "_code_groups", "code_letters", "code_digits"]
+    );
+    assert_eq!(
+        out.column("_code_groups")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 2]
+    );
+    assert_eq!(
+        out.column("code_digits")?
+            .str()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        ["12", "34"]
+    );
+    Ok(())
+}
+
+#[test]
+fn extract_filter_no_matches() -> Result<()> {
+    let df = df!("code" => ["ab", "cd"])?;
+    let out = apply(
+        df,
+        r#"extract = { column = "code", pattern = '(?<digits>\d+)', filter = true }"#,
+    )?;
+    assert_eq!(out.height(), 0);
+    assert_eq!(out.get_column_names_str(), ["code", "digits"]);
+    Ok(())
+}
+
+#[test]
+fn any_all() -> Result<()> {
+    let df = df!(
+        "g" => ["a", "a", "b", "b", "c"],
+        "flag" => [Some(true), Some(false), Some(false), None, Some(true)],
+    )?;
+    let out = apply(
+        df,
+        r#"
+        [group_by]
+        exprs = [{ expr.col = "g" }]
+        agg = [
+            { expr.col = "flag", ops = [{ any = {} }, { alias = "any" }] },
+            { expr.col = "flag", ops = [{ all = {} }, { alias = "all" }] },
+            { expr.col = "flag", ops = [{ any = { ignore_nulls = false } }, { alias = "any_nulls" }] },
+        ]
+        "#,
+    )?
+    .sort(["g"], Default::default())?;
+    let flags =
+        |name| -> Result<Vec<Option<bool>>> { Ok(out.column(name)?.bool()?.into_iter().collect()) };
+    assert_eq!(flags("any")?, [Some(true), Some(false), Some(true)]);
+    assert_eq!(flags("all")?, [Some(false), Some(false), Some(true)]);
+    assert_eq!(flags("any_nulls")?, [Some(true), None, Some(true)]);
+    Ok(())
+}
+
+#[test]
+fn time_zones() -> Result<()> {
+    let noon = 1_704_110_400_000i64; // 2024-01-01T12:00:00
+    let df = Series::new("ts".into(), [noon])
+        .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))?
+        .into_frame();
+    let out = apply(
+        df,
+        r#"
+        select = [
+            { expr.col = "ts", ops = [{ dt = { replace_time_zone = "America/New_York" } }, { alias = "replaced" }] },
+            { expr.col = "ts", ops = [{ dt = { replace_time_zone = "UTC" } }, { dt = { convert_time_zone = "America/New_York" } }, { alias = "converted" }] },
+        ]
+        "#,
+    )?;
+    let timestamp = |name| -> Result<i64> {
+        Ok(out
+            .column(name)?
+            .cast(&DataType::Int64)?
+            .i64()?
+            .get(0)
+            .unwrap())
+    };
+    // Noon in New York is 17:00 UTC.
+    assert_eq!(timestamp("replaced")?, noon + 5 * 60 * 60 * 1000);
+    // Converting keeps the same instant, displayed as 07:00 in New York.
+    assert_eq!(timestamp("converted")?, noon);
+    for name in ["replaced", "converted"] {
+        assert!(out
+            .column(name)?
+            .dtype()
+            .to_string()
+            .contains("America/New_York"));
+    }
+    Ok(())
+}
+
+#[test]
+fn strftime() -> Result<()> {
+    let df = Series::new("ts".into(), [1_704_110_400_000i64])
+        .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))?
+        .into_frame();
+    let out = apply(
+        df.clone(),
+        r#"select = [{ expr.col = "ts", ops = [{ dt = { strftime = "%Y-%m-%d" } }] }]"#,
+    )?;
+    assert_eq!(out.column("ts")?.str()?.get(0), Some("2024-01-01"));
+    assert!(apply(
+        df,
+        r#"select = [{ expr.col = "ts", ops = [{ dt = { strftime = "%Y-%Q" } }] }]"#,
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn offset_by() -> Result<()> {
+    let df = Series::new("d".into(), [19753i32]) // 2024-01-31
+        .cast(&DataType::Date)?
+        .into_frame();
+    let out = apply(
+        df,
+        r#"select = [{ expr.col = "d", ops = [{ dt = { offset_by = "1mo" } }] }]"#,
+    )?;
+    assert_eq!(
+        out.column("d")?.cast(&DataType::Int32)?.i32()?.get(0),
+        Some(19782) // 2024-02-29
+    );
+    Ok(())
+}
+
+#[test]
+fn csv_float_format() -> Result<()> {
+    use exports::ExportItem;
+
+    let dir = scratch_dir("csv_float_format")?;
+    for sink in [true, false] {
+        let export: ExportItem = toml::from_str(&format!(
+            "type = 'csv'\nfolder = '{}'\nname = 'export_{sink}'\nsink = {sink}\nfloat_precision = 2\nfloat_scientific = false",
+            dir.display()
+        ))?;
+        export.export(df!("a" => [0.5f64, 12345678901.234])?.lazy())?;
+        assert_eq!(
+            std::fs::read_to_string(dir.join(format!("export_{sink}.csv")))?,
+            "a\n0.50\n12345678901.23\n"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn csv_datetime_format() -> Result<()> {
+    use exports::ExportItem;
+
+    let dir = scratch_dir("csv_datetime_format")?;
+    let df = DataFrame::new(vec![
+        Series::new("ts".into(), [1_704_110_400_000i64])
+            .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))?
+            .into(),
+        Series::new("d".into(), [19753i32])
+            .cast(&DataType::Date)?
+            .into(),
+    ])?;
+    for sink in [true, false] {
+        let export: ExportItem = toml::from_str(&format!(
+            "type = 'csv'\nfolder = '{}'\nname = 'export_{sink}'\nsink = {sink}\ndatetime_format = '%Y-%m-%dT%H:%M:%SZ'\ndate_column_format = '%d/%m/%Y'",
+            dir.display()
+        ))?;
+        export.export(df.clone().lazy())?;
+        assert_eq!(
+            std::fs::read_to_string(dir.join(format!("export_{sink}.csv")))?,
+            "ts,d\n2024-01-01T12:00:00Z,31/01/2024\n"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn parquet_directory_source() -> Result<()> {
+    let dir = scratch_dir("parquet_directory_source")?;
+    for (name, values) in [("a", [1i64, 2]), ("b", [3i64, 4])] {
+        ParquetWriter::new(std::fs::File::create(dir.join(format!("{name}.parquet")))?)
+            .finish(&mut df!("n" => values)?)?;
+    }
+    std::fs::write(dir.join("notes.txt"), "not parquet")?;
+    let source = sources::ParquetSource {
+        paths: std::sync::Arc::from([PlPath::Local(dir.as_path().into())]),
+        extension: None,
+        schema: None,
+        include_file_path: None,
+        rechunk: None,
+    };
+    let df = source.load()?.collect()?;
+    assert_eq!(
+        df.column("n")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 2, 3, 4]
+    );
+    Ok(())
+}
+
+#[test]
+fn collect_n() -> Result<()> {
+    let df = df!("a" => [1i64, 2, 3, 4])?;
+    let df = apply(df, "collect = { n = 2 }")?;
+    assert_eq!(
+        df.column("a")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 2]
+    );
+    Ok(())
+}
+
+#[test]
+fn duplicate_output_column() -> Result<()> {
+    let df = df!("a" => [1i64, 2], "b" => [3i64, 4])?;
+    let err = apply(
+        df.clone(),
+        r#"select = [{ expr.col = "a", ops = [{ alias = "x" }] }, { expr.col = "b", ops = [{ alias = "x" }] }]"#,
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "duplicate output column: x");
+    let err = apply(
+        df.clone(),
+        r#"with_columns = [{ expr.col = "a", ops = [{ alias = "x" }] }, { expr.col = "b", ops = [{ alias = "x" }] }]"#,
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "duplicate output column: x");
+    // Replacing an existing column is still allowed.
+    let out = apply(
+        df,
+        r#"with_columns = [{ expr.col = "b", ops = [{ alias = "a" }] }]"#,
+    )?;
+    assert_eq!(out.get_column_names_str(), ["a", "b"]);
+    Ok(())
+}
+
+#[test]
+fn drop_nan() -> Result<()> {
+    let df = df!("a" => [Some(1.0f64), Some(f64::NAN), None, Some(2.0)])?;
+    let out = apply(df, r#"select = [{ expr.col = "a", ops = ["drop_nan"] }]"#)?;
+    assert_eq!(
+        out.column("a")?.f64()?.into_iter().collect::<Vec<_>>(),
+        [Some(1.0), None, Some(2.0)]
+    );
+    Ok(())
+}
+
+#[test]
+fn run_base_dir() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("base_dir")?;
+    std::fs::create_dir_all(dir.join("configs"))?;
+    std::fs::create_dir_all(dir.join("volume"))?;
+    std::fs::write(dir.join("volume/data.csv"), "a\n1\n2\n")?;
+    std::fs::write(
+        dir.join("configs/config.toml"),
+        r#"
+        run.base_dir = "../volume"
+        source.data.csv.path = "data.csv"
+
+        [[exports]]
+        type = "csv"
+        folder = "output"
+        name = "export"
+        "#,
+    )?;
+    Config::from_path(dir.join("configs/config.toml"), |config| config.run())?;
+    assert_eq!(
+        std::fs::read_to_string(dir.join("volume/output/export.csv"))?,
+        "a\n1\n2\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn set_existing_column() -> Result<()> {
+    let df = df!("a" => [1i64, 2], "b" => [3i64, 4])?;
+    let out = apply(
+        df,
+        r#"set = { expr.col = "b", ops = [{ add = { expr.col = "a" } }, { alias = "a" }] }"#,
+    )?;
+    assert_eq!(out.get_column_names_str(), ["a", "b"]);
+    assert_eq!(
+        out.column("a")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [4, 6]
+    );
+    Ok(())
+}
+
+#[test]
+fn column_selectors() -> Result<()> {
+    let df = df!(
+        "id" => [1i64, 2],
+        "name" => ["x", "y"],
+        "score" => [0.5f64, 1.5],
+        "weight" => [2.0f64, 3.0],
+    )?;
+    let out = apply(df.clone(), r#"drop.dtypes = ["Float64"]"#)?;
+    assert_eq!(out.get_column_names_str(), ["id", "name"]);
+    let out = apply(df.clone(), r#"drop.except.names = ["id", "score"]"#)?;
+    assert_eq!(out.get_column_names_str(), ["id", "score"]);
+    let out = apply(
+        df,
+        r#"drop.intersection = [{ dtypes = ["Float64"] }, { except.regex = "^s" }]"#,
+    )?;
+    assert_eq!(out.get_column_names_str(), ["id", "name", "score"]);
+    let err = apply(df.clone(), r#"drop.names = ["id", "nmae"]"#).unwrap_err();
+    assert!(format!("{err:#}").contains("no column named nmae"));
+    // Serialized polars selectors, as accepted before column selectors were introduced, still work.
+    let out = apply(df, r#"drop.ByName = { names = ["id"], strict = true }"#)?;
+    assert_eq!(out.get_column_names_str(), ["name", "score", "weight"]);
+    Ok(())
+}
+
+#[test]
+fn empty_glob() -> Result<()> {
+    use sources::CsvSource;
+
+    let dir = scratch_dir("empty_glob")?;
+    let pattern = dir.join("*.csv");
+    let err = load(&format!("csv = {{ path = '{}' }}", pattern.display())).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("no files match {}", pattern.display())
+    );
+
+    let source = CsvSource {
+        path: pattern.try_into()?,
+        separator: None,
+        has_header: None,
+        schema: Some(sources::Schema(Schema::from_iter([Field::new(
+            "id".into(),
+            DataType::Int64,
+        )]))),
+        include_file_path: None,
+        concat: Default::default(),
+        extension: None,
+        allow_empty: true,
+        rechunk: None,
+    };
+    let df = source.load()?.collect()?;
+    assert_eq!(df.height(), 0);
+    assert_eq!(df.get_column_names_str(), ["id"]);
+    Ok(())
+}
+
+#[test]
+fn source_rechunk() -> Result<()> {
+    let dir = scratch_dir("source_rechunk")?;
+    std::fs::write(dir.join("1.csv"), "a\n1\n2\n")?;
+    std::fs::write(dir.join("2.csv"), "a\n3\n4\n")?;
+    let df = load(&format!(
+        "csv = {{ path = '{}', rechunk = true }}",
+        dir.join("*.csv").display()
+    ))?;
+    assert_eq!(df.height(), 4);
+    assert_eq!(df.column("a")?.n_chunks(), 1);
+    Ok(())
+}
+
+#[test]
+fn group_by_truncated_key() -> Result<()> {
+    let hour = 60 * 60 * 1000i64;
+    let day = 24 * hour;
+    let df = Series::new("ts".into(), [hour, 5 * hour, day + 2 * hour])
+        .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))?
+        .into_frame();
+    let out = apply(
+        df,
+        r#"
+        [group_by]
+        exprs = [{ expr.col = "ts", ops = [{ dt = { truncate = "1d" } }, { alias = "day" }] }]
+        agg = [{ expr = "len", ops = [{ alias = "events" }] }]
+        maintain_order = true
+        "#,
+    )?;
+    assert_eq!(out.get_column_names_str(), ["day", "events"]);
+    assert_eq!(
+        out.column("day")?
+            .cast(&DataType::Int64)?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [0, day]
+    );
+    assert_eq!(
+        out.column("events")?
+            .cast(&DataType::Int64)?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [2, 1]
+    );
+    Ok(())
+}
+
+#[test]
+fn explode_empty_lists() -> Result<()> {
+    let df = df!(
+        "id" => [1i64, 2, 3],
+        "values" => [
+            Series::new("".into(), [1i64, 2]),
+            Series::new("".into(), Vec::<i64>::new()),
+            Series::new("".into(), [3i64]),
+        ],
+    )?;
+    let ids = |df: &DataFrame| -> Result<Vec<i64>> {
+        Ok(df.column("id")?.i64()?.into_no_null_iter().collect())
+    };
+    let out = apply(df.clone(), r#"explode = { columns.names = ["values"] }"#)?;
+    assert_eq!(ids(&out)?, [1, 1, 2, 3]);
+    assert_eq!(
+        out.column("values")?.i64()?.into_iter().collect::<Vec<_>>(),
+        [Some(1), Some(2), None, Some(3)]
+    );
+    let out = apply(
+        df.clone(),
+        r#"explode = { columns.names = ["values"], empty = "drop" }"#,
+    )?;
+    assert_eq!(ids(&out)?, [1, 1, 3]);
+    // The columns can be given on their own, including as the serialized polars selector accepted before options were added.
+    let out = apply(df.clone(), r#"explode.names = ["values"]"#)?;
+    assert_eq!(ids(&out)?, [1, 1, 2, 3]);
+    let out = apply(
+        df,
+        r#"explode.ByName = { names = ["values"], strict = true }"#,
+    )?;
+    assert_eq!(out.height(), 4);
+    Ok(())
+}
+
+#[test]
+fn safe_div() -> Result<()> {
+    let df = df!("a" => [6.0f64, 5.0, 4.0], "b" => [2.0f64, 0.0, 4.0])?;
+    let out = apply(
+        df,
+        r#"
+        select = [
+            { expr.col = "a", ops = [{ safe_div = { by = { expr.col = "b" } } }] },
+            { expr.col = "a", ops = [{ safe_div = { by = { expr.col = "b" }, fallback = { expr.lit = "0", ops = [{ cast = "Float64" }] } } }, { alias = "with_fallback" }] },
+        ]
+        "#,
+    )?;
+    assert_eq!(
+        out.column("a")?.f64()?.into_iter().collect::<Vec<_>>(),
+        [Some(3.0), None, Some(1.0)]
+    );
+    assert_eq!(
+        out.column("with_fallback")?
+            .f64()?
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [Some(3.0), Some(0.0), Some(1.0)]
+    );
+    Ok(())
+}
+
+#[test]
+fn rename_non_strict() -> Result<()> {
+    let df = df!("a" => [1i64], "b" => [2i64])?;
+    assert!(apply(df.clone(), r#"rename.map = { a = "x", missing = "y" }"#,).is_err());
+    let out = apply(
+        df,
+        r#"rename.map = { columns = { a = "x", missing = "y" }, strict = false }"#,
+    )?;
+    assert_eq!(out.get_column_names_str(), ["x", "b"]);
+    // The schema allows both the plain mapping and the form with options.
+    let schema = serde_json::to_value(schemars::schema_for!(transforms::Rename))?;
+    assert_eq!(
+        schema["definitions"]["RenameMap"]["anyOf"]
+            .as_array()
+            .map(Vec::len),
+        Some(2)
+    );
+    Ok(())
+}
+
+#[test]
+fn filter_eq() -> Result<()> {
+    let df = df!(
+        "id" => [1i64, 2, 3, 4],
+        "status" => ["active", "active", "inactive", "active"],
+        "region" => ["EU", "US", "EU", "EU"],
+    )?;
+    let out = apply(
+        df.clone(),
+        r#"filter_eq = { status = "active", region = "EU" }"#,
+    )?;
+    assert_eq!(
+        out.column("id")?
+            .i64()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        [1, 4]
+    );
+    let out = apply(df.clone(), r#"filter_eq = { id = 3 }"#)?;
+    assert_eq!(out.column("status")?.str()?.get(0), Some("inactive"));
+    let df = df!(
+        "score" => [Some(1.0f64), Some(1.5), None],
+        "active" => [true, false, true],
+        "day" => ["2024-01-01", "2024-01-02", "2024-01-01"],
+    )?
+    .lazy()
+    .with_columns([col("day").str().to_date(StrptimeOptions::default())])
+    .collect()?;
+    let out = apply(df.clone(), r#"filter_eq = { score = 1, active = true }"#)?;
+    assert_eq!(out.height(), 1);
+    let out = apply(df, r#"filter_eq = { day = "2024-01-01" }"#)?;
+    assert_eq!(out.height(), 2);
+    Ok(())
+}
+
+#[test]
+fn unique_per_group() -> Result<()> {
+    let df = df!(
+        "user" => ["a", "a", "a", "b", "b"],
+        "tag" => ["x", "y", "x", "z", "z"],
+    )?;
+    let out = apply(
+        df,
+        r#"
+        [group_by]
+        exprs = [{ expr.col = "user" }]
+        agg = [{ expr.col = "tag", ops = [{ unique = { maintain_order = true } }] }]
+        maintain_order = true
+        "#,
+    )?;
+    let tags = out
+        .column("tag")?
+        .list()?
+        .into_no_null_iter()
+        .map(|tags| -> Result<Vec<String>> {
+            Ok(tags.str()?.into_no_null_iter().map(str::to_owned).collect())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(tags, [vec!["x", "y"], vec!["z"]]);
+    Ok(())
+}
+
+#[test]
+fn literal_json_source() -> Result<()> {
+    let df = load(
+        r#"
+        [literal]
+        format = "json"
+        data = """
+        [
+            { "code": "A", "meta": { "label": "Active", "rank": 1 } },
+            { "code": "I", "meta": { "label": "Inactive", "rank": 2 } }
+        ]
+        """
+        "#,
+    )?;
+    assert_eq!(df.get_column_names_str(), ["code", "meta"]);
+    assert!(matches!(df.column("meta")?.dtype(), DataType::Struct(_)));
+    let labels = df.column("meta")?.struct_()?.field_by_name("label")?;
+    assert_eq!(
+        labels.str()?.into_no_null_iter().collect::<Vec<_>>(),
+        ["Active", "Inactive"]
+    );
+    Ok(())
+}
+
+#[test]
+fn config_vars() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("config_vars")?;
+    std::fs::write(dir.join("2024-01-01.csv"), "a\n1\n")?;
+    std::fs::write(dir.join("2024-01-02.csv"), "a\n2\n")?;
+    std::fs::write(
+        dir.join("config.toml"),
+        r#"
+        vars = { date = "2024-01-01" }
+        source.data.csv.path = "${date}.csv"
+        transforms = [{ rename.regex = { pattern = "^(?<name>.*)$", replacement = "$${name}_value" } }]
+        "#,
+    )?;
+    let value = |config: Config| -> Result<i64> {
+        let df = config.load()?.collect()?;
+        Ok(df.column("a_value")?.i64()?.get(0).unwrap())
+    };
+    assert_eq!(Config::from_path(dir.join("config.toml"), value)?, 1);
+    let vars = [("date".to_owned(), "2024-01-02".to_owned())].into();
+    assert_eq!(
+        Config::with_vars(vars, || Config::from_path(dir.join("config.toml"), value))?,
+        2
+    );
+
+    std::fs::write(
+        dir.join("missing.toml"),
+        r#"source.data.csv.path = "${nope}.csv""#,
+    )?;
+    let err = Config::from_path(dir.join("missing.toml"), Ok).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(utils::Error::UnknownVariable(name)) if name == "nope"
+    ));
+    Ok(())
+}
+
+#[test]
+fn circular_config_source() -> Result<()> {
+    let _lock = CURRENT_DIR.lock().unwrap();
+    let dir = scratch_dir("circular_config_source")?;
+    std::fs::write(dir.join("a.toml"), r#"source.data.config.path = "b.toml""#)?;
+    std::fs::write(dir.join("b.toml"), r#"source.data.config.path = "a.toml""#)?;
+    let err = Config::from_path(dir.join("a.toml"), |config| config.load()).unwrap_err();
+    assert!(
+        matches!(
+            err.downcast_ref(),
+            Some(utils::Error::CircularConfigSource(_))
+        ),
+        "{err:?}"
+    );
+
+    std::fs::write(dir.join("data.csv"), "a\n1\n")?;
+    std::fs::write(
+        dir.join("inner.toml"),
+        r#"source.data.csv.path = "data.csv""#,
+    )?;
+    std::fs::write(
+        dir.join("middle.toml"),
+        r#"source.data.config.path = "inner.toml""#,
+    )?;
+    std::fs::write(
+        dir.join("outer.toml"),
+        r#"source.data.config = { path = "middle.toml", max_depth = 1 }"#,
+    )?;
+    let err = Config::from_path(dir.join("outer.toml"), |config| config.load()).unwrap_err();
+    assert!(
+        matches!(err.downcast_ref(), Some(utils::Error::ConfigSourceDepth(1))),
+        "{err:?}"
+    );
+    Ok(())
+}
+
+#[test]
+fn optimization_toggles() -> Result<()> {
+    let plan = |run: &str| -> Result<String> {
+        let config: Config = toml::from_str(&format!(
+            r#"
+            source.data.literal = {{ format = "csv", data = "a,b\n1,2\n" }}
+            transforms = [{{ select = [{{ expr.col = "a" }}] }}]
+            run = {run}
+            "#
+        ))?;
+        Ok(config.load()?.explain(true)?)
+    };
+    assert!(plan("{}")?.contains("1/2 COLUMNS"));
+    assert!(!plan("{ optimizations.projection_pushdown = false }")?.contains("1/2 COLUMNS"));
+    Ok(())
+}
+
+#[test]
+fn format_expression() -> Result<()> {
+    let df = df!("region" => ["EU", "US"], "id" => [1i64, 2])?;
+    let out = apply(
+        df.clone(),
+        r#"select = [{ expr.format = { template = "{}/{}", args = [{ expr.col = "region" }, { expr.col = "id" }] }, ops = [{ alias = "key" }] }]"#,
+    )?;
+    assert_eq!(
+        out.column("key")?
+            .str()?
+            .into_no_null_iter()
+            .collect::<Vec<_>>(),
+        ["EU/1", "US/2"]
+    );
+    assert!(apply(
+        df,
+        r#"select = [{ expr.format = { template = "{}/{}", args = [{ expr.col = "region" }] } }]"#,
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn parquet_row_group_size() -> Result<()> {
+    use exports::ExportItem;
+
+    let dir = scratch_dir("parquet_row_group_size")?;
+    let export: ExportItem = toml::from_str(&format!(
+        "type = 'parquet'\nfolder = '{}'\nname = 'export'\nrow_group_size = 2",
+        dir.display()
+    ))?;
+    export.export(df!("a" => [1i64, 2, 3, 4, 5])?.lazy())?;
+    let mut reader = ParquetReader::new(std::fs::File::open(dir.join("export.parquet"))?);
+    let row_groups = reader
+        .get_metadata()?
+        .row_groups
+        .iter()
+        .map(|group| group.num_rows())
+        .collect::<Vec<_>>();
+    assert_eq!(row_groups, [2, 2, 1]);
+    Ok(())
+}
+
+#[test]
+fn csv_batch_size() -> Result<()> {
+    use exports::ExportItem;
+
+    let dir = scratch_dir("csv_batch_size")?;
+    for sink in [true, false] {
+        let export: ExportItem = toml::from_str(&format!(
+            "type = 'csv'\nfolder = '{}'\nname = 'export_{sink}'\nsink = {sink}\nbatch_size = 1",
+            dir.display()
+        ))?;
+        export.export(df!("a" => [1i64, 2, 3])?.lazy())?;
+        assert_eq!(
+            std::fs::read_to_string(dir.join(format!("export_{sink}.csv")))?,
+            "a\n1\n2\n3\n"
+        );
+    }
+    assert!(
+        toml::from_str::<ExportItem>("type = 'csv'\nfolder = '.'\nname = 'x'\nbatch_size = 0")
+            .is_err()
+    );
+    Ok(())
+}
 
-// TODO
 #[test]
-fn it_works() {}
+fn struct_fields() -> Result<()> {
+    let df = df!("a" => [1i64, 2], "b" => ["x", "y"], "c" => [true, false], "d" => [0.5f64, 1.5])?
+        .into_struct("s".into())
+        .into_series()
+        .into_frame();
+    let out = apply(
+        df,
+        r#"select = [{ expr.col = "s", ops = [{ struct = { fields = ["d", "b"] } }] }]"#,
+    )?;
+    assert_eq!(out.get_column_names_str(), ["s"]);
+    let fields = out.column("s")?.struct_()?.fields_as_series();
+    assert_eq!(
+        fields.iter().map(|s| s.name().as_str()).collect::<Vec<_>>(),
+        ["d", "b"]
+    );
+    assert_eq!(
+        fields[1].str()?.into_no_null_iter().collect::<Vec<_>>(),
+        ["x", "y"]
+    );
+    Ok(())
+}
@@ -1,14 +1,22 @@
 //! Transformations that modify a [`LazyFrame`] and pass it on to other transformations, or to be exported.
 
 use crate::{
-    expressions::{Expression, ExpressionChain, Match},
+    definitions,
+    expressions::{any_value, Expression, ExpressionChain, Match},
     sources::Loader,
+    utils::{DataType, Error},
 };
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use polars::{lazy::prelude::*, prelude::*};
+use regex::Regex;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, fmt::Debug};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Debug,
+    ops::Deref,
+};
+use tracing::{info, warn};
 
 /// Trait for transformations that take a [`LazyFrame`] as input and modify it.
 pub trait Transform: Serialize + for<'a> Deserialize<'a> + JsonSchema + Debug {
@@ -52,6 +60,26 @@ pub enum TransformItem {
     Concat(Concat),
     /// Limit data to the first N rows.
     Limit(u32),
+    /// Transpose the data, turning rows into columns and columns into rows.
+    Transpose(Transpose),
+    /// Replace values in a column according to a mapping of old values to new.
+    MapValues(MapValues),
+    /// Bin a numeric column into labeled buckets.
+    Cut(Cut),
+    /// One-hot encode columns into indicator columns.
+    ToDummies(ToDummies),
+    /// Insert rows for missing intervals in a time series.
+    Upsample(Upsample),
+    /// Apply a list of transforms declared by name in the configuration's `definitions`.
+    Ref(String),
+    /// Cast multiple columns to new datatypes at once.
+    Cast(CastColumns),
+    /// Drop all columns of the given datatypes, such as the all-null columns that some sources produce.
+    DropByType(DropByType),
+    /// Fill null values across many columns at once.
+    FillNull(FillNullFrame),
+    /// Filter rows where each of the given columns equals the given value.
+    FilterEq(FilterEq),
 }
 
 impl Transform for TransformItem {
@@ -73,6 +101,16 @@ impl Transform for TransformItem {
             Self::GroupBy(transform) => transform.transform(lf),
             Self::Concat(transform) => transform.transform(lf),
             Self::Limit(n) => Ok(lf.limit(*n)),
+            Self::Transpose(transform) => transform.transform(lf),
+            Self::MapValues(transform) => transform.transform(lf),
+            Self::Cut(transform) => transform.transform(lf),
+            Self::ToDummies(transform) => transform.transform(lf),
+            Self::Upsample(transform) => transform.transform(lf),
+            Self::Ref(name) => definitions::transform(name, lf),
+            Self::Cast(transform) => transform.transform(lf),
+            Self::DropByType(transform) => transform.transform(lf),
+            Self::FillNull(transform) => transform.transform(lf),
+            Self::FilterEq(transform) => transform.transform(lf),
         }
     }
 }
@@ -81,25 +119,173 @@ impl Transform for TransformItem {
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Select(Vec<ExpressionChain>);
 
+impl Select {
+    /// Select the given expressions.
+    pub fn new<I: IntoIterator<Item = ExpressionChain>>(exprs: I) -> Self {
+        Self(exprs.into_iter().collect())
+    }
+}
+
+impl From<Select> for TransformItem {
+    fn from(value: Select) -> Self {
+        Self::Select(value)
+    }
+}
+
 impl Transform for Select {
     fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
-        Ok(lf.select(
-            self.0
+        let exprs = self
+            .0
+            .iter()
+            .map(<_>::expr)
+            .collect::<Result<Vec<Expr>, _>>()?;
+        check_output_names(&lf, &exprs)?;
+        Ok(lf.select(exprs.as_slice()))
+    }
+}
+
+/// Check that no two of `exprs` produce a column with the same name when evaluated against `lf`,
+/// which polars would otherwise either reject with a less specific error or resolve by keeping only one.
+///
+/// Each expression is wrapped in a struct under a unique name, so the output names of all of them
+/// can be read from the fields of a single resolved schema. If that schema cannot be resolved,
+/// the check is skipped, leaving polars to report the problem.
+fn check_output_names(lf: &LazyFrame, exprs: &[Expr]) -> Result<()> {
+    let wrapped = exprs
+        .iter()
+        .enumerate()
+        .map(|(i, expr)| as_struct(vec![expr.clone()]).alias(i.to_string()))
+        .collect::<Vec<_>>();
+    let Ok(schema) = lf.clone().select(wrapped).collect_schema() else {
+        return Ok(());
+    };
+    let mut seen = HashSet::new();
+    for dtype in schema.iter_values() {
+        let DataType::Struct(fields) = dtype else {
+            continue;
+        };
+        for field in fields {
+            if !seen.insert(field.name()) {
+                return Err(Error::DuplicateColumn(field.name().to_string()).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Select columns by name, datatype or pattern, or by combining other selectors.
+///
+/// ```toml
+/// # Drop every column except `id` and `name`.
+/// drop.except.names = ["id", "name"]
+/// ```
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnSelector {
+    /// Every column.
+    All,
+    /// Columns with the given names. Fails if any of them does not exist.
+    Names(Vec<String>),
+    /// Columns with any of the given datatypes.
+    Dtypes(Vec<DataType>),
+    /// Columns whose names match a regex.
+    Regex(String),
+    /// Columns matched by any of the given selectors.
+    Union(Vec<ColumnSelector>),
+    /// Columns matched by every one of the given selectors.
+    Intersection(Vec<ColumnSelector>),
+    /// Columns not matched by the given selector.
+    Except(Box<ColumnSelector>),
+    /// A serialized polars selector, as accepted before column selectors were introduced.
+    #[serde(untagged)]
+    Polars(Selector),
+}
+
+impl ColumnSelector {
+    /// Find the names of the columns in `schema` that are matched, where `schema` is the schema of `lf`.
+    fn matches(&self, lf: &LazyFrame, schema: &Schema) -> Result<HashSet<PlSmallStr>> {
+        Ok(match self {
+            Self::All => schema.iter_names().cloned().collect(),
+            Self::Names(names) => {
+                if let Some(missing) = names.iter().find(|name| !schema.contains(name)) {
+                    return Err(Error::UnknownColumn(missing.clone()).into());
+                }
+                names
+                    .iter()
+                    .map(|name| PlSmallStr::from(name.as_str()))
+                    .collect()
+            }
+            Self::Dtypes(dtypes) => schema
                 .iter()
-                .map(<_>::expr)
-                .collect::<Result<Vec<Expr>, _>>()?
-                .as_slice(),
-        ))
+                .filter(|(_, dtype)| dtypes.iter().any(|d| d.deref() == *dtype))
+                .map(|(name, _)| name.clone())
+                .collect(),
+            Self::Regex(pattern) => {
+                let regex = Regex::new(pattern)?;
+                schema
+                    .iter_names()
+                    .filter(|name| regex.is_match(name))
+                    .cloned()
+                    .collect()
+            }
+            Self::Union(selectors) => {
+                let mut matched = HashSet::new();
+                for selector in selectors {
+                    matched.extend(selector.matches(lf, schema)?);
+                }
+                matched
+            }
+            Self::Intersection(selectors) => {
+                let mut matched: HashSet<PlSmallStr> = schema.iter_names().cloned().collect();
+                for selector in selectors {
+                    let other = selector.matches(lf, schema)?;
+                    matched.retain(|name| other.contains(name));
+                }
+                matched
+            }
+            Self::Except(selector) => {
+                let excluded = selector.matches(lf, schema)?;
+                schema
+                    .iter_names()
+                    .filter(|name| !excluded.contains(*name))
+                    .cloned()
+                    .collect()
+            }
+            Self::Polars(selector) => lf
+                .clone()
+                .select([Expr::from(selector.clone())])
+                .collect_schema()?
+                .iter_names()
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Resolve the names of the matched columns of `lf`, in order.
+    pub(crate) fn names(&self, lf: &mut LazyFrame) -> Result<Vec<PlSmallStr>> {
+        let schema = lf.collect_schema()?;
+        let matched = self.matches(lf, &schema)?;
+        Ok(schema
+            .iter_names()
+            .filter(|name| matched.contains(*name))
+            .cloned()
+            .collect())
+    }
+
+    /// Resolve the matched columns of `lf` to a [`Selector`] of their names.
+    pub(crate) fn resolve(&self, lf: &mut LazyFrame) -> Result<Selector> {
+        Ok(cols(self.names(lf)?))
     }
 }
 
-/// Select a series of expressions with applied operations. Wraps [`polars::lazy::prelude::LazyFrame::select`].
+/// Drop the selected columns.
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
-pub struct Drop(Selector);
+pub struct Drop(ColumnSelector);
 
 impl Transform for Drop {
-    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
-        Ok(lf.drop(self.0.clone()))
+    fn transform(&self, mut lf: LazyFrame) -> Result<LazyFrame> {
+        let selector = self.0.resolve(&mut lf)?;
+        Ok(lf.drop(selector))
     }
 }
 
@@ -108,15 +294,25 @@ impl Transform for Drop {
 #[serde(rename_all = "snake_case")]
 pub enum Rename {
     /// Rename using a direct mapping of old names to new.
-    Map(BTreeMap<String, String>),
+    Map(RenameMap),
+    /// Rename every column by replacing all matches of a regex in its name.
+    /// The replacement may refer to capture groups, e.g. `$1`.
+    Regex {
+        /// Regex to search column names for.
+        pattern: String,
+        /// Text to replace each match with.
+        replacement: String,
+    },
     // /// Rename all columns using a prefix.
     // Prefix(String),
 }
 
 impl Transform for Rename {
-    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
+    fn transform(&self, mut lf: LazyFrame) -> Result<LazyFrame> {
         match self {
-            Self::Map(columns) => Ok(lf.rename(columns.keys(), columns.values(), true)),
+            Self::Map(RenameMap { columns, strict }) => {
+                Ok(lf.rename(columns.keys(), columns.values(), strict.unwrap_or(true)))
+            }
             // TODO: Fix successive uses of this not stacking properly
             // Self::Prefix(_prefix) => {
             //     let prefix = _prefix.clone();
@@ -124,6 +320,70 @@ impl Transform for Rename {
             //         .name()
             //         .map(move |col| Ok(format!("{}{}", prefix, col)))]))
             // }
+            Self::Regex {
+                pattern,
+                replacement,
+            } => {
+                let regex = Regex::new(pattern)?;
+                let (old, new): (Vec<_>, Vec<_>) = lf
+                    .collect_schema()?
+                    .iter_names()
+                    .filter_map(|name| {
+                        let renamed = regex.replace_all(name, replacement.as_str());
+                        (renamed != name.as_str()).then(|| (name.clone(), renamed.into_owned()))
+                    })
+                    .unzip();
+                Ok(lf.rename(old, new, true))
+            }
+        }
+    }
+}
+
+/// Mapping of old column names to new, written either directly or with options.
+///
+/// ```toml
+/// rename.map = { label = "a_label" }
+/// rename.map = { columns = { label = "a_label" }, strict = false }
+/// ```
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(from = "RenameMapRepr")]
+pub struct RenameMap {
+    /// Mapping of old names to new.
+    pub columns: BTreeMap<String, String>,
+    /// Fail if a column to rename does not exist. If false, such columns are skipped. Defaults to true.
+    pub strict: Option<bool>,
+}
+
+/// The ways a [`RenameMap`] can be written.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged, deny_unknown_fields)]
+enum RenameMapRepr {
+    Options {
+        /// Mapping of old names to new.
+        columns: BTreeMap<String, String>,
+        /// Fail if a column to rename does not exist. If false, such columns are skipped. Defaults to true.
+        strict: Option<bool>,
+    },
+    Columns(BTreeMap<String, String>),
+}
+
+impl JsonSchema for RenameMap {
+    fn schema_name() -> String {
+        "RenameMap".to_owned()
+    }
+    fn json_schema(generator: &mut schemars::r#gen::SchemaGenerator) -> schemars::schema::Schema {
+        RenameMapRepr::json_schema(generator)
+    }
+}
+
+impl From<RenameMapRepr> for RenameMap {
+    fn from(value: RenameMapRepr) -> Self {
+        match value {
+            RenameMapRepr::Options { columns, strict } => Self { columns, strict },
+            RenameMapRepr::Columns(columns) => Self {
+                columns,
+                strict: None,
+            },
         }
     }
 }
@@ -132,6 +392,19 @@ impl Transform for Rename {
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Filter(Vec<ExpressionChain>);
 
+impl Filter {
+    /// Keep only rows for which all of the given expressions are true.
+    pub fn new<I: IntoIterator<Item = ExpressionChain>>(exprs: I) -> Self {
+        Self(exprs.into_iter().collect())
+    }
+}
+
+impl From<Filter> for TransformItem {
+    fn from(value: Filter) -> Self {
+        Self::Filter(value)
+    }
+}
+
 impl Transform for Filter {
     fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
         Ok(self
@@ -143,13 +416,43 @@ impl Transform for Filter {
     }
 }
 
+/// Filter rows where each of the given columns equals the given value, as a shorthand for a `filter` of `eq` ops.
+/// Each value is converted to the datatype of its column, so e.g. dates can be written as strings.
+/// A null value matches rows where the column is null.
+///
+/// ```toml
+/// filter_eq = { status = "active", region = "EU", count = 3, active = true }
+/// ```
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct FilterEq(BTreeMap<String, serde_json::Value>);
+
+impl Transform for FilterEq {
+    fn transform(&self, mut lf: LazyFrame) -> Result<LazyFrame> {
+        let schema = lf.collect_schema()?;
+        for (column, value) in &self.0 {
+            let dtype = schema.try_get(column)?.clone();
+            let predicate = match any_value(value)? {
+                AnyValue::Null => col(column.as_str()).is_null(),
+                value => col(column.as_str())
+                    .eq(lit(Scalar::new(value.dtype(), value)).strict_cast(dtype)),
+            };
+            lf = lf.filter(predicate);
+        }
+        Ok(lf)
+    }
+}
+
 /// Extract capture groups from a regex into separate columns.
+/// Each column is named after its capture group, e.g. `(?<year>\d{4})` produces a column named `year`.
 #[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Extract {
     #[serde(flatten)]
     matcher: Match,
+    /// Only keep rows where the pattern matches. If no rows match, the result is empty.
     #[serde(default)]
     filter: bool,
+    /// Prefix to add to the name of each extracted column, to avoid collisions with existing columns.
+    prefix: Option<String>,
 }
 
 impl Transform for Extract {
@@ -158,16 +461,20 @@ impl Transform for Extract {
             lf = lf.filter(self.matcher.expr()?);
         }
 
-        // TODO: See if this can be done without an intermediate alias
-        let alias = format!("_{}_groups", &self.matcher.column);
+        // The groups are extracted into an intermediate struct column, which must not replace an existing column.
+        let schema = lf.collect_schema()?;
+        let mut alias = format!("_{}_groups", &self.matcher.column);
+        while schema.contains(&alias) {
+            alias.push('_');
+        }
+        let mut groups = col(&self.matcher.column)
+            .str()
+            .extract_groups(self.matcher.pattern.as_str())?;
+        if let Some(prefix) = &self.prefix {
+            groups = groups.name().prefix_fields(prefix);
+        }
         lf = lf
-            .select([
-                col("*"),
-                col(&self.matcher.column)
-                    .str()
-                    .extract_groups(self.matcher.pattern.as_str())?
-                    .alias(alias.as_str()),
-            ])
+            .select([col("*"), groups.alias(alias.as_str())])
             .unnest(Selector::ByName {
                 names: Arc::new([alias.into()]),
                 strict: true,
@@ -177,13 +484,83 @@ impl Transform for Extract {
     }
 }
 
-/// Apply [`polars::lazy::prelude::LazyFrame::unnest`] to the given struct columns.
-#[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
-pub struct Unnest(Selector);
+/// Apply [`polars::lazy::prelude::LazyFrame::unnest`] to the given struct columns, written either as just
+/// the columns or with options.
+///
+/// ```toml
+/// unnest.names = ["address"]
+/// unnest = { columns.names = ["address"], prefix = "address_" }
+/// ```
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(from = "UnnestRepr")]
+pub struct Unnest {
+    /// The struct columns to unnest.
+    columns: ColumnSelector,
+    /// Prefix to add to the name of each field before unnesting, to avoid collisions with existing columns.
+    prefix: Option<String>,
+    /// Suffix to add to the name of each field before unnesting, to avoid collisions with existing columns.
+    suffix: Option<String>,
+}
+
+/// The ways an [`Unnest`] can be written.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged, deny_unknown_fields)]
+enum UnnestRepr {
+    Options {
+        /// The struct columns to unnest.
+        columns: ColumnSelector,
+        /// Prefix to add to the name of each field before unnesting, to avoid collisions with existing columns.
+        prefix: Option<String>,
+        /// Suffix to add to the name of each field before unnesting, to avoid collisions with existing columns.
+        suffix: Option<String>,
+    },
+    Columns(ColumnSelector),
+}
+
+impl From<UnnestRepr> for Unnest {
+    fn from(value: UnnestRepr) -> Self {
+        match value {
+            UnnestRepr::Options {
+                columns,
+                prefix,
+                suffix,
+            } => Self {
+                columns,
+                prefix,
+                suffix,
+            },
+            UnnestRepr::Columns(columns) => Self {
+                columns,
+                prefix: None,
+                suffix: None,
+            },
+        }
+    }
+}
+
+impl JsonSchema for Unnest {
+    fn schema_name() -> String {
+        "Unnest".to_owned()
+    }
+    fn json_schema(generator: &mut schemars::r#gen::SchemaGenerator) -> schemars::schema::Schema {
+        UnnestRepr::json_schema(generator)
+    }
+}
 
 impl Transform for Unnest {
-    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
-        Ok(lf.unnest(self.0.clone()))
+    fn transform(&self, mut lf: LazyFrame) -> Result<LazyFrame> {
+        let columns = self.columns.resolve(&mut lf)?;
+        if self.prefix.is_some() || self.suffix.is_some() {
+            let mut expr = Expr::from(columns.clone());
+            if let Some(prefix) = &self.prefix {
+                expr = expr.name().prefix_fields(prefix);
+            }
+            if let Some(suffix) = &self.suffix {
+                expr = expr.name().suffix_fields(suffix);
+            }
+            lf = lf.with_columns([expr]);
+        }
+        Ok(lf.unnest(columns))
     }
 }
 
@@ -242,15 +619,31 @@ impl From<&DuplicateKeep> for UniqueKeepStrategy {
 #[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
 pub struct DropDuplicates {
     /// Columns to check for duplicate values (defaults to all columns).
-    pub subset: Option<Selector>,
+    pub subset: Option<ColumnSelector>,
     /// Which duplicate record (if any) to keep.
+    /// `first` and `last` refer to row order, so the data should be sorted with `sort_by` beforehand
+    /// and `maintain_order` set for the choice to be meaningful.
     #[serde(default)]
     pub keep: DuplicateKeep,
+    /// Whether to keep the remaining rows in their original order, making `first` and `last` deterministic.
+    /// This is more expensive than an unordered deduplication.
+    #[serde(default)]
+    pub maintain_order: bool,
 }
 
 impl Transform for DropDuplicates {
-    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
-        Ok(lf.unique(self.subset.clone(), UniqueKeepStrategy::from(&self.keep)))
+    fn transform(&self, mut lf: LazyFrame) -> Result<LazyFrame> {
+        let keep = UniqueKeepStrategy::from(&self.keep);
+        let subset = self
+            .subset
+            .as_ref()
+            .map(|subset| subset.resolve(&mut lf))
+            .transpose()?;
+        Ok(if self.maintain_order {
+            lf.unique_stable(subset, keep)
+        } else {
+            lf.unique(subset, keep)
+        })
     }
 }
 
@@ -285,7 +678,7 @@ pub struct Join {
 
 impl Transform for Join {
     fn transform(&self, lf1: LazyFrame) -> Result<LazyFrame> {
-        let lf2 = self.right.load()?;
+        let lf2 = self.right.load().context("join.right")?;
         Ok(lf1.join(
             lf2,
             self.left_on
@@ -310,13 +703,13 @@ impl Transform for Join {
     }
 }
 
-/// Add a column with the given expression.
+/// Add a column with the given expression, or replace the column in place if one with the same name exists.
 #[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
 pub struct Set(ExpressionChain);
 
 impl Transform for Set {
     fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
-        Ok(lf.select([col("*"), self.0.expr()?]))
+        Ok(lf.with_columns([self.0.expr()?]))
     }
 }
 
@@ -326,60 +719,182 @@ pub struct WithColumns(Vec<ExpressionChain>);
 
 impl Transform for WithColumns {
     fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
-        Ok(lf.with_columns(
-            self.0
-                .iter()
-                .map(<_>::expr)
-                .collect::<Result<Vec<Expr>, _>>()?
-                .as_slice(),
-        ))
+        let exprs = self
+            .0
+            .iter()
+            .map(<_>::expr)
+            .collect::<Result<Vec<Expr>, _>>()?;
+        check_output_names(&lf, &exprs)?;
+        Ok(lf.with_columns(exprs.as_slice()))
     }
 }
 
-/// Explode a column with list elements.
-#[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
-pub struct Explode(Selector);
+/// What to do with rows whose list is empty when exploding.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExplodeEmpty {
+    /// Keep the row, with a null value, so that every input row produces at least one output row.
+    #[default]
+    Null,
+    /// Drop the row.
+    Drop,
+}
+
+/// Explode columns with list elements into a row per element, written either as just the columns or with options.
+///
+/// ```toml
+/// explode.names = ["tags"]
+/// explode = { columns.names = ["tags"], empty = "drop" }
+/// ```
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(from = "ExplodeRepr")]
+pub struct Explode {
+    /// The list columns to explode.
+    columns: ColumnSelector,
+    /// What to do with rows whose list is empty. Null lists always produce a single null row.
+    empty: ExplodeEmpty,
+}
+
+/// The ways an [`Explode`] can be written.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged, deny_unknown_fields)]
+enum ExplodeRepr {
+    Options {
+        /// The list columns to explode.
+        columns: ColumnSelector,
+        /// What to do with rows whose list is empty. Null lists always produce a single null row.
+        #[serde(default)]
+        empty: ExplodeEmpty,
+    },
+    Columns(ColumnSelector),
+}
+
+impl From<ExplodeRepr> for Explode {
+    fn from(value: ExplodeRepr) -> Self {
+        match value {
+            ExplodeRepr::Options { columns, empty } => Self { columns, empty },
+            ExplodeRepr::Columns(columns) => Self {
+                columns,
+                empty: ExplodeEmpty::default(),
+            },
+        }
+    }
+}
+
+impl JsonSchema for Explode {
+    fn schema_name() -> String {
+        "Explode".to_owned()
+    }
+    fn json_schema(generator: &mut schemars::r#gen::SchemaGenerator) -> schemars::schema::Schema {
+        ExplodeRepr::json_schema(generator)
+    }
+}
 
 impl Transform for Explode {
-    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
-        Ok(lf.explode(self.0.clone()))
+    fn transform(&self, mut lf: LazyFrame) -> Result<LazyFrame> {
+        let names = self.columns.names(&mut lf)?;
+        let is_empty = |name: &PlSmallStr| col(name.clone()).list().len().eq(lit(0));
+        lf = match self.empty {
+            ExplodeEmpty::Null => lf.with_columns(
+                names
+                    .iter()
+                    .map(|name| {
+                        when(is_empty(name))
+                            .then(NULL.lit())
+                            .otherwise(col(name.clone()))
+                            .alias(name.clone())
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            ExplodeEmpty::Drop => names.iter().fold(lf, |lf, name| {
+                lf.filter(is_empty(name).not().fill_null(lit(true)))
+            }),
+        };
+        Ok(lf.explode(cols(names)))
     }
 }
 
 /// Run the pipeline up to the current point and collect the result in memory.
 #[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
-pub struct Collect {}
+pub struct Collect {
+    /// Whether to execute the pipeline using the streaming engine.
+    #[serde(default)]
+    pub streaming: bool,
+    /// Whether to profile the pipeline and log the time spent in each node of the query plan.
+    /// Profiling always uses the default engine, so `streaming` is ignored if this is set.
+    #[serde(default)]
+    pub profile: bool,
+    /// Only collect the first N rows, capping the memory used at this point of the pipeline.
+    /// Unlike a `limit` transform, this always materializes the data.
+    pub n: Option<u32>,
+}
+
+impl Collect {
+    /// Collect the data, also returning the per-node timings if profiling is enabled.
+    pub(crate) fn collect(&self, mut lf: LazyFrame) -> Result<(DataFrame, Option<DataFrame>)> {
+        if let Some(n) = self.n {
+            lf = lf.limit(n);
+        }
+        Ok(if self.profile {
+            let (df, timings) = lf.profile()?;
+            (df, Some(timings))
+        } else if self.streaming {
+            (lf.collect_with_engine(Engine::Streaming)?, None)
+        } else {
+            (lf.collect()?, None)
+        })
+    }
+}
 
 impl Transform for Collect {
     fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
-        Ok(lf.collect()?.lazy())
+        let (df, timings) = self.collect(lf)?;
+        if let Some(timings) = timings {
+            info!("collect profile:\n{timings}");
+        }
+        Ok(df.lazy())
     }
 }
 
 /// Run one or more aggregations on the given expressions.
+///
+/// Keys can be computed expressions, and are named after their column unless given an alias:
+///
+/// ```toml
+/// [group_by]
+/// exprs = [{ expr.col = "timestamp", ops = [{ dt.truncate = "1d" }, { alias = "day" }] }]
+/// agg = [{ expr = "len", ops = [{ alias = "events" }] }]
+/// ```
 #[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
 pub struct GroupBy {
+    /// Expressions to group by, which become the leading columns of the output.
     exprs: Vec<ExpressionChain>,
+    /// Aggregations to compute for each group.
     agg: Vec<ExpressionChain>,
+    /// Keep groups in the order their keys first appear, rather than an arbitrary order.
+    #[serde(default)]
+    maintain_order: bool,
 }
 
 impl Transform for GroupBy {
     fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
-        Ok(lf
-            .group_by(
-                self.exprs
-                    .iter()
-                    .map(|e| e.expr())
-                    .collect::<Result<Vec<Expr>>>()?
-                    .as_slice(),
-            )
-            .agg(
-                self.agg
-                    .iter()
-                    .map(|e| e.expr())
-                    .collect::<Result<Vec<Expr>>>()?
-                    .as_slice(),
-            ))
+        let exprs = self
+            .exprs
+            .iter()
+            .map(|e| e.expr())
+            .collect::<Result<Vec<Expr>>>()?;
+        let agg = self
+            .agg
+            .iter()
+            .map(|e| e.expr())
+            .collect::<Result<Vec<Expr>>>()?;
+        check_output_names(&lf, &[exprs.as_slice(), agg.as_slice()].concat())?;
+        let groups = if self.maintain_order {
+            lf.group_by_stable(exprs.as_slice())
+        } else {
+            lf.group_by(exprs.as_slice())
+        };
+        Ok(groups.agg(agg.as_slice()))
     }
 }
 
@@ -396,8 +911,15 @@ pub enum ConcatType {
 pub struct Concat {
     pub sources: Vec<Loader>,
     pub how: ConcatType,
-    #[serde(default)]
-    pub args: UnionArgs,
+    /// Whether to rechunk the result into contiguous memory. Defaults to false.
+    pub rechunk: Option<bool>,
+    /// Whether to load the sources in parallel. Defaults to true.
+    pub parallel: Option<bool>,
+    /// Whether to cast columns to a common supertype if their datatypes differ, e.g. `Int32` and `Int64` to `Int64`.
+    /// Defaults to false.
+    pub to_supertypes: Option<bool>,
+    /// Deprecated: use `rechunk`, `parallel` and `to_supertypes` instead, which take precedence over it.
+    pub args: Option<UnionArgs>,
 }
 
 impl Transform for Concat {
@@ -409,11 +931,307 @@ impl Transform for Concat {
             .collect::<Result<Vec<LazyFrame>>>()?;
         lazy_frames.insert(0, lf1);
 
+        if self.args.is_some() {
+            warn!("concat.args is deprecated, use concat.rechunk, concat.parallel and concat.to_supertypes instead");
+        }
+        let base = self.args.unwrap_or_default();
+        let args = UnionArgs {
+            rechunk: self.rechunk.unwrap_or(base.rechunk),
+            parallel: self.parallel.unwrap_or(base.parallel),
+            to_supertypes: self.to_supertypes.unwrap_or(base.to_supertypes),
+            ..base
+        };
         let lf = match self.how {
-            ConcatType::Diagonal => concat_lf_diagonal(lazy_frames.as_slice(), self.args)?,
-            ConcatType::Horizontal => concat_lf_horizontal(lazy_frames.as_slice(), self.args)?,
-            ConcatType::Vertical => concat(lazy_frames.as_slice(), self.args)?,
+            ConcatType::Diagonal => concat_lf_diagonal(lazy_frames.as_slice(), args)?,
+            ConcatType::Horizontal => concat_lf_horizontal(lazy_frames.as_slice(), args)?,
+            ConcatType::Vertical => concat(lazy_frames.as_slice(), args)?,
         };
         Ok(lf)
     }
 }
+
+/// Transpose the data, turning rows into columns and columns into rows.
+/// There is no lazy equivalent, so like [`Collect`], this runs the pipeline up to the current point
+/// and materializes the full result in memory.
+#[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
+pub struct Transpose {
+    /// Name of a column to hold the original column names. If unset, the original names are discarded.
+    pub header_name: Option<String>,
+    /// Names to give the transposed columns. Defaults to `column_0`, `column_1`, etc.
+    pub column_names: Option<Vec<String>>,
+}
+
+impl Transform for Transpose {
+    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
+        let mut df = lf.collect()?.transpose(self.header_name.as_deref(), None)?;
+        if let Some(names) = &self.column_names {
+            let names = self
+                .header_name
+                .iter()
+                .chain(names.iter())
+                .map(String::as_str)
+                .collect::<Vec<&str>>();
+            df.set_column_names(names)?;
+        }
+        Ok(df.lazy())
+    }
+}
+
+/// Replace values in a column according to a mapping of old values to new.
+#[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
+pub struct MapValues {
+    /// The column to replace values in.
+    pub column: String,
+    /// Mapping of existing values to their replacements.
+    pub mapping: BTreeMap<String, String>,
+    /// Value to use for anything not present in the mapping. If unset, unmapped values are left as-is.
+    pub default: Option<ExpressionChain>,
+    /// Data type of the resulting column.
+    pub return_dtype: Option<DataType>,
+}
+
+impl Transform for MapValues {
+    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
+        let old = lit(Series::new(
+            "old".into(),
+            self.mapping.keys().cloned().collect::<Vec<String>>(),
+        ));
+        let new = lit(Series::new(
+            "new".into(),
+            self.mapping.values().cloned().collect::<Vec<String>>(),
+        ));
+        let return_dtype = self.return_dtype.as_ref().map(|dt| dt.deref().clone());
+        let expr = match &self.default {
+            Some(default) => {
+                col(&self.column).replace_strict(old, new, Some(default.expr()?), return_dtype)
+            }
+            None => match return_dtype {
+                Some(dtype) => col(&self.column).replace(old, new).cast(dtype),
+                None => col(&self.column).replace(old, new),
+            },
+        };
+        Ok(lf.with_columns([expr]))
+    }
+}
+
+/// Bin a numeric column into buckets separated by the given breakpoints, replacing it with a categorical column.
+#[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
+pub struct Cut {
+    /// The column to bin.
+    pub column: String,
+    /// Breakpoints between buckets, in ascending order.
+    pub breaks: Vec<f64>,
+    /// Names of the buckets. Must contain exactly one more label than there are breaks.
+    pub labels: Option<Vec<String>>,
+    /// Whether buckets include their lower bound rather than their upper bound.
+    #[serde(default)]
+    pub left_closed: bool,
+}
+
+impl Transform for Cut {
+    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
+        if let Some(labels) = &self.labels {
+            if labels.len() != self.breaks.len() + 1 {
+                return Err(Error::Other(format!(
+                    "cut on column {} requires {} labels for {} breaks, got {}",
+                    self.column,
+                    self.breaks.len() + 1,
+                    self.breaks.len(),
+                    labels.len()
+                ))
+                .into());
+            }
+        }
+        Ok(lf.with_columns([col(&self.column).cut(
+            self.breaks.clone(),
+            self.labels.clone(),
+            self.left_closed,
+            false,
+        )]))
+    }
+}
+
+/// One-hot encode columns, replacing each with one indicator column per distinct value.
+/// The output columns depend on the data itself, so like [`Collect`], this runs the pipeline up to the current point
+/// and materializes the full result in memory.
+#[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
+pub struct ToDummies {
+    /// The columns to encode.
+    pub columns: Vec<String>,
+    /// Whether to drop the first indicator column of each encoded column.
+    #[serde(default)]
+    pub drop_first: bool,
+}
+
+impl Transform for ToDummies {
+    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
+        Ok(lf
+            .collect()?
+            .columns_to_dummies(
+                self.columns.iter().map(String::as_str).collect(),
+                None,
+                self.drop_first,
+            )?
+            .lazy())
+    }
+}
+
+/// How to fill values in rows inserted by [`Upsample`].
+#[derive(Clone, Deserialize, Serialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UpsampleFill {
+    /// Leave inserted values null.
+    #[default]
+    None,
+    /// Fill inserted values with the previous non-null value.
+    Forward,
+    /// Fill inserted values with the next non-null value.
+    Backward,
+}
+
+/// Insert rows for missing intervals in a time series.
+/// This runs the pipeline up to the current point and materializes the full result in memory.
+#[derive(Clone, Deserialize, Serialize, Debug, JsonSchema)]
+pub struct Upsample {
+    /// The date or datetime column to upsample. Must be sorted in ascending order, within each group if `by` is set.
+    pub time_column: String,
+    /// Interval between rows, as a duration string (e.g. `1d`, `1h`).
+    pub every: String,
+    /// Columns to group by, upsampling each group separately.
+    #[serde(default)]
+    pub by: Vec<String>,
+    /// How to fill values in inserted rows.
+    #[serde(default)]
+    pub fill: UpsampleFill,
+}
+
+impl Transform for Upsample {
+    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
+        let every = Duration::try_parse(&self.every)?;
+        let df = lf.collect()?;
+        // Groups are upsampled separately, so the time column only needs to be sorted within each of them.
+        let groups = if self.by.is_empty() {
+            vec![df.clone()]
+        } else {
+            df.partition_by_stable(self.by.clone(), true)?
+        };
+        for group in &groups {
+            if !group
+                .column(&self.time_column)?
+                .as_materialized_series()
+                .is_sorted(SortOptions::default())?
+            {
+                return Err(Error::Other(format!(
+                    "upsample column {} must be sorted in ascending order",
+                    self.time_column
+                ))
+                .into());
+            }
+        }
+        let df = df.upsample(self.by.clone(), &self.time_column, every)?;
+        Ok(match self.fill {
+            UpsampleFill::None => df,
+            UpsampleFill::Forward => df.fill_null(FillNullStrategy::Forward(None))?,
+            UpsampleFill::Backward => df.fill_null(FillNullStrategy::Backward(None))?,
+        }
+        .lazy())
+    }
+}
+
+/// Cast columns to new datatypes.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct CastColumns {
+    /// Mapping of column names to the datatype to cast each to.
+    pub columns: BTreeMap<String, DataType>,
+    /// Whether to raise an error if a value cannot be cast, rather than setting it to null.
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl Transform for CastColumns {
+    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
+        Ok(lf.with_columns(
+            self.columns
+                .iter()
+                .map(|(column, dtype)| {
+                    let dtype = dtype.deref().clone();
+                    if self.strict {
+                        col(column.as_str()).strict_cast(dtype)
+                    } else {
+                        col(column.as_str()).cast(dtype)
+                    }
+                })
+                .collect::<Vec<_>>(),
+        ))
+    }
+}
+
+/// Drop all columns whose datatype is one of those given. Shorthand for `drop.dtypes`.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct DropByType(Vec<DataType>);
+
+impl Transform for DropByType {
+    fn transform(&self, lf: LazyFrame) -> Result<LazyFrame> {
+        Drop(ColumnSelector::Dtypes(self.0.clone())).transform(lf)
+    }
+}
+
+/// Strategies for filling null values based on other values in the same column.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FillNullFrameStrategy {
+    /// Fill with the previous non-null value.
+    Forward,
+    /// Fill with the next non-null value.
+    Backward,
+    /// Fill with the mean of the column.
+    Mean,
+    /// Fill with the minimum of the column.
+    Min,
+    /// Fill with the maximum of the column.
+    Max,
+    /// Fill with zero.
+    Zero,
+    /// Fill with one.
+    One,
+}
+
+/// Fill null values in all columns, or a subset of them.
+/// Exactly one of `value` or `strategy` must be provided.
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct FillNullFrame {
+    /// Columns to fill (defaults to all columns).
+    pub subset: Option<ColumnSelector>,
+    /// Expression to fill nulls with.
+    pub value: Option<ExpressionChain>,
+    /// Strategy to fill nulls with.
+    pub strategy: Option<FillNullFrameStrategy>,
+}
+
+impl Transform for FillNullFrame {
+    fn transform(&self, mut lf: LazyFrame) -> Result<LazyFrame> {
+        let columns: Expr = match &self.subset {
+            Some(subset) => subset.resolve(&mut lf)?.into(),
+            None => all().into(),
+        };
+        let filled = match (&self.value, self.strategy) {
+            (Some(value), None) => columns.fill_null(value.expr()?),
+            (None, Some(strategy)) => match strategy {
+                FillNullFrameStrategy::Forward => columns.forward_fill(None),
+                FillNullFrameStrategy::Backward => columns.backward_fill(None),
+                FillNullFrameStrategy::Mean => columns.clone().fill_null(columns.mean()),
+                FillNullFrameStrategy::Min => columns.clone().fill_null(columns.min()),
+                FillNullFrameStrategy::Max => columns.clone().fill_null(columns.max()),
+                FillNullFrameStrategy::Zero => columns.fill_null(lit(0)),
+                FillNullFrameStrategy::One => columns.fill_null(lit(1)),
+            },
+            _ => {
+                return Err(Error::Other(
+                    "fill_null requires exactly one of value or strategy".to_owned(),
+                )
+                .into())
+            }
+        };
+        Ok(lf.with_columns([filled]))
+    }
+}
@@ -7,6 +7,7 @@ use std::{
     ops::Deref,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 /// Errors that can be encountered during configuration parsing.
@@ -15,6 +16,48 @@ pub enum Error {
     /// Returned when attempting to run a configuration does not contain any exports.
     #[error("must define at least one export")]
     NoExports,
+    /// Returned when a configuration has no source and does not extend one that does.
+    #[error("must define a source or extend a configuration that does")]
+    NoSource,
+    /// Returned when a configuration's `extends` chain leads back to itself.
+    #[error("circular extends chain detected at {}", .0.display())]
+    CircularExtends(PathBuf),
+    /// Returned when an export's output file already exists and its policy is to fail.
+    #[error("export destination {} already exists", .0.display())]
+    ExportExists(PathBuf),
+    /// Returned when incremental processing is enabled and a source has no new or changed input files.
+    #[error("no new input files since the last run")]
+    NoNewFiles,
+    /// Returned when a `ref` names a definition that does not exist.
+    #[error("no definition named {0}")]
+    UnknownDefinition(String),
+    /// Returned when a definition refers back to itself, directly or indirectly.
+    #[error("circular reference detected at definition {0}")]
+    CircularDefinition(String),
+    /// Returned when a run does not finish within its time limit.
+    #[error("run did not finish within {0:?}")]
+    Timeout(Duration),
+    /// Returned when more than one expression in a transform would produce a column with the same name.
+    #[error("duplicate output column: {0}")]
+    DuplicateColumn(String),
+    /// Returned when a source's path matches no files.
+    #[error("no files match {}", .0.display())]
+    NoMatchingFiles(PathBuf),
+    /// Returned when a configuration refers to a variable that is not defined anywhere.
+    #[error("no variable named {0}")]
+    UnknownVariable(String),
+    /// Returned when configuration sources lead back to a configuration that is already being loaded.
+    #[error("circular config source detected at {}", .0.display())]
+    CircularConfigSource(PathBuf),
+    /// Returned when configuration sources are nested more deeply than allowed.
+    #[error("config sources are nested more than {0} deep")]
+    ConfigSourceDepth(usize),
+    /// Returned when a selector names a column that does not exist.
+    #[error("no column named {0}")]
+    UnknownColumn(String),
+    /// Returned when a run is started while a previous run that timed out is still executing.
+    #[error("a previous run timed out and is still executing")]
+    TimedOutRunActive,
     /// Other unspecified error encountered during parsing.
     #[error("{0}")]
     Other(String),
@@ -39,20 +82,38 @@ where
     }
 }
 
-/// One or more paths that are canonicalized (see [`std::fs::canonicalize`]) and guaranteed to exist.
+/// Paths matching a glob pattern, which are canonicalized (see [`std::fs::canonicalize`]) and guaranteed to exist.
+/// The pattern may match no paths at all.
 #[derive(Clone, Serialize, Deserialize, Debug)]
-#[serde(try_from = "PathBuf")]
-pub struct CanonicalPaths(Arc<Vec<PathBuf>>);
+#[serde(try_from = "PathBuf", into = "PathBuf")]
+pub struct CanonicalPaths {
+    pattern: PathBuf,
+    paths: Arc<Vec<PathBuf>>,
+}
+
+impl CanonicalPaths {
+    /// The pattern the paths were matched by, as written in the configuration.
+    pub fn pattern(&self) -> &Path {
+        &self.pattern
+    }
+}
 
 impl TryFrom<PathBuf> for CanonicalPaths {
     type Error = anyhow::Error;
     fn try_from(value: PathBuf) -> std::result::Result<Self, Self::Error> {
-        Ok(Self(Arc::new(
-            glob(value.to_str().context("paths must be valid unicode")?)?
-                .into_iter()
-                .map(|res| res.map(|p| p.canonicalize()))
-                .collect::<Result<Result<Vec<_>, _>, _>>()??,
-        )))
+        let paths = glob(value.to_str().context("paths must be valid unicode")?)?
+            .map(|res| res.map(|p| p.canonicalize()))
+            .collect::<Result<Result<Vec<_>, _>, _>>()??;
+        Ok(Self {
+            pattern: value,
+            paths: Arc::new(paths),
+        })
+    }
+}
+
+impl From<CanonicalPaths> for PathBuf {
+    fn from(value: CanonicalPaths) -> Self {
+        value.pattern
     }
 }
 
@@ -68,8 +129,30 @@ impl JsonSchema for CanonicalPaths {
 impl Deref for CanonicalPaths {
     type Target = Arc<Vec<PathBuf>>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.paths
+    }
+}
+
+/// Replace each directory in `paths` with the files directly inside it that have the given extension, in name order.
+/// Other paths are kept as they are.
+pub(crate) fn expand_dirs<'a, I>(paths: I, extension: &str) -> Result<Vec<PathBuf>>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    let mut expanded = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            expanded.push(path.to_owned());
+            continue;
+        }
+        let mut files = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        files.retain(|file| file.is_file() && file.extension().is_some_and(|ext| ext == extension));
+        files.sort();
+        expanded.extend(files);
     }
+    Ok(expanded)
 }
 
 /// A single path that is canonicalized (see [`std::fs::canonicalize`]) and guaranteed to exist.
@@ -126,3 +209,87 @@ impl Deref for DataType {
         &self.0
     }
 }
+
+/// A sensitive value, such as a database connection string, that can be kept out of the configuration file.
+/// The value is only read when it is needed, and literal values are redacted when debug-printed or serialized.
+///
+/// ```toml
+/// connection = { env = "DATABASE_URL" }
+/// ```
+#[derive(Clone, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Secret {
+    /// Read the value from an environment variable.
+    Env(String),
+    /// Read the value from a file, ignoring trailing whitespace. Like other paths in a configuration,
+    /// relative paths are resolved against `run.base_dir` if it is set, or else the configuration's directory.
+    File(PathBuf),
+    /// Run a program with the given arguments and use its output, ignoring trailing whitespace.
+    Command(Vec<String>),
+    /// Use the value as written. Only suitable for configurations that are not shared or committed.
+    Literal(String),
+}
+
+const REDACTED: &str = "<redacted>";
+
+impl Secret {
+    /// Resolve the secret to its value.
+    pub fn resolve(&self) -> Result<String> {
+        Ok(match self {
+            Self::Env(name) => std::env::var(name).with_context(|| {
+                format!("failed to read secret from environment variable {name}")
+            })?,
+            Self::File(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read secret from {}", path.display()))?
+                .trim_end()
+                .to_owned(),
+            Self::Command(args) => {
+                let (program, args) = args
+                    .split_first()
+                    .ok_or_else(|| Error::Other("secret command must not be empty".to_owned()))?;
+                let output = std::process::Command::new(program)
+                    .args(args)
+                    .output()
+                    .with_context(|| format!("failed to run secret command {program}"))?;
+                if !output.status.success() {
+                    return Err(Error::Other(format!(
+                        "secret command {program} exited with {}",
+                        output.status
+                    ))
+                    .into());
+                }
+                String::from_utf8(output.stdout)?.trim_end().to_owned()
+            }
+            Self::Literal(value) => value.clone(),
+        })
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Env(name) => f.debug_tuple("Env").field(name).finish(),
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+            Self::Command(args) => f.debug_tuple("Command").field(args).finish(),
+            Self::Literal(_) => f.debug_tuple("Literal").field(&REDACTED).finish(),
+        }
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Env(name) => serializer.serialize_newtype_variant("Secret", 0, "env", name),
+            Self::File(path) => serializer.serialize_newtype_variant("Secret", 1, "file", path),
+            Self::Command(args) => {
+                serializer.serialize_newtype_variant("Secret", 2, "command", args)
+            }
+            Self::Literal(_) => {
+                serializer.serialize_newtype_variant("Secret", 3, "literal", REDACTED)
+            }
+        }
+    }
+}